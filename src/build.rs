@@ -69,7 +69,7 @@ fn main() {
     let safe_width = if width == 0 { 800 } else { width };
     let safe_height = if height == 0 { 800 } else { height };
     let safe_color_mode = match color_mode.as_str() {
-        "Direction" | "Age" | "Curl" => color_mode,
+        "Direction" | "Age" | "Curl" | "Speed" => color_mode,
         _ => "Direction".to_string(),
     };
 
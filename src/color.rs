@@ -0,0 +1,144 @@
+//! Color-space helpers shared by the color modes and the fade pass.
+
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.003_130_8 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Per-channel lookup table that scales an sRGB byte by `scale` in linear
+/// light. Every nonzero value drops by at least one step so dim trails still
+/// reach black despite rounding.
+pub fn fade_lut(scale: f32) -> [u8; 256] {
+    fade_toward_lut(scale, 0)
+}
+
+/// Like `fade_lut`, but moves each value toward `target` instead of black:
+/// `target + (c - target) * scale` in linear light, always by at least one
+/// step so the background is eventually reached exactly.
+pub fn fade_toward_lut(scale: f32, target: u8) -> [u8; 256] {
+    let target_linear = srgb_to_linear(target as f32 / 255.0);
+    let mut lut = [0u8; 256];
+    for (c, out) in lut.iter_mut().enumerate() {
+        let c = c as u8;
+        let linear = target_linear + (srgb_to_linear(c as f32 / 255.0) - target_linear) * scale;
+        let faded = (linear_to_srgb(linear) * 255.0).round().clamp(0.0, 255.0) as u8;
+        *out = match c.cmp(&target) {
+            std::cmp::Ordering::Greater => faded.min(c - 1).max(target),
+            std::cmp::Ordering::Less => faded.max(c + 1).min(target),
+            std::cmp::Ordering::Equal => target,
+        };
+    }
+    lut
+}
+
+/// Extended Reinhard tone mapping of `v` into 0..=1: compresses values
+/// toward `white`, which lands exactly on 1. A `white` of 1 leaves 0..=1
+/// unchanged.
+pub fn tone_map(v: f32, white: f32) -> f32 {
+    let white = white.max(1.0);
+    (v * (1.0 + v / (white * white)) / (1.0 + v)).clamp(0.0, 1.0)
+}
+
+/// Per-channel lookup table raising each normalized byte to `1 / gamma`, so
+/// values above 1 lift the midtones and below 1 deepen them.
+pub fn gamma_lut(gamma: f32) -> [u8; 256] {
+    let exponent = 1.0 / gamma.max(0.01);
+    let mut lut = [0u8; 256];
+    for (c, out) in lut.iter_mut().enumerate() {
+        *out = ((c as f32 / 255.0).powf(exponent) * 255.0).round().clamp(0.0, 255.0) as u8;
+    }
+    lut
+}
+
+/// Per-channel lookup table that scales an sRGB byte by `scale` in linear
+/// light, rounding to the nearest byte.
+pub fn scale_lut(scale: f32) -> [u8; 256] {
+    let mut lut = [0u8; 256];
+    for (c, out) in lut.iter_mut().enumerate() {
+        let linear = srgb_to_linear(c as f32 / 255.0) * scale;
+        *out = (linear_to_srgb(linear) * 255.0).round().clamp(0.0, 255.0) as u8;
+    }
+    lut
+}
+
+pub fn hsv_to_rgb(mut h: f32, s: f32, v: f32) -> (u8, u8, u8) {
+    let s = s.clamp(0.0, 1.0);
+    let v = v.clamp(0.0, 1.0);
+    h = h.fract();
+    if h < 0.0 {
+        h += 1.0;
+    }
+    let i = (h * 6.0).floor() as i32;
+    let f = h * 6.0 - i as f32;
+    let p = v * (1.0 - s);
+    let q = v * (1.0 - f * s);
+    let t = v * (1.0 - (1.0 - f) * s);
+    let (r, g, b) = match i.rem_euclid(6) {
+        0 => (v, t, p),
+        1 => (q, v, p),
+        2 => (p, v, t),
+        3 => (p, q, v),
+        4 => (t, p, v),
+        _ => (v, p, q),
+    };
+    (
+        (r * 255.0).round().clamp(0.0, 255.0) as u8,
+        (g * 255.0).round().clamp(0.0, 255.0) as u8,
+        (b * 255.0).round().clamp(0.0, 255.0) as u8,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn close(a: (u8, u8, u8), b: (u8, u8, u8)) -> bool {
+        a.0.abs_diff(b.0) <= 1 && a.1.abs_diff(b.1) <= 1 && a.2.abs_diff(b.2) <= 1
+    }
+
+    #[test]
+    fn primary_hues() {
+        assert_eq!(hsv_to_rgb(0.0, 1.0, 1.0), (255, 0, 0));
+        assert_eq!(hsv_to_rgb(1.0 / 3.0, 1.0, 1.0), (0, 255, 0));
+        assert_eq!(hsv_to_rgb(2.0 / 3.0, 1.0, 1.0), (0, 0, 255));
+    }
+
+    proptest! {
+        // With any inputs, even out of range, the brightest channel is v and
+        // the dimmest is v * (1 - s), after clamping both to 0..=1
+        #[test]
+        fn channels_bounded_by_value(h in -10.0f32..10.0, s in -1.0f32..2.0, v in -1.0f32..2.0) {
+            let (r, g, b) = hsv_to_rgb(h, s, v);
+            let (s, v) = (s.clamp(0.0, 1.0), v.clamp(0.0, 1.0));
+            let max = (v * 255.0).round() as u8;
+            let min = (v * (1.0 - s) * 255.0).round() as u8;
+            prop_assert!(r.max(g).max(b).abs_diff(max) <= 1);
+            prop_assert!(r.min(g).min(b).abs_diff(min) <= 1);
+        }
+
+        #[test]
+        fn zero_saturation_is_gray(h in -10.0f32..10.0, v in 0.0f32..=1.0) {
+            let gray = (v * 255.0).round() as u8;
+            prop_assert_eq!(hsv_to_rgb(h, 0.0, v), (gray, gray, gray));
+        }
+
+        #[test]
+        fn hue_wraps_every_turn(h in 0.0f32..1.0, s in 0.0f32..=1.0, v in 0.0f32..=1.0, turns in -3i32..=3) {
+            let base = hsv_to_rgb(h, s, v);
+            let wrapped = hsv_to_rgb(h + turns as f32, s, v);
+            prop_assert!(close(base, wrapped), "{:?} vs {:?}", base, wrapped);
+        }
+    }
+}
@@ -0,0 +1,31 @@
+//! Easing curves applied to normalized quantities before they become color.
+
+use crate::params::AgeEasing;
+
+/// Maps `t` in 0..1 through `easing`, keeping 0 and 1 fixed so a cyclic
+/// mapping still wraps without a seam.
+pub fn ease(easing: AgeEasing, t: f32) -> f32 {
+    match easing {
+        AgeEasing::Linear => t,
+        AgeEasing::InOut => ease_in_out(t),
+        AgeEasing::Exponential => exponential(t),
+    }
+}
+
+/// Cubic ease-in-out: slow at both ends, fastest in the middle.
+pub fn ease_in_out(t: f32) -> f32 {
+    if t < 0.5 {
+        4.0 * t * t * t
+    } else {
+        1.0 - (2.0 - 2.0 * t).powi(3) * 0.5
+    }
+}
+
+/// Exponential ease-in: lingers near 0, then rises steeply to 1.
+pub fn exponential(t: f32) -> f32 {
+    if t <= 0.0 {
+        0.0
+    } else {
+        2f32.powf(10.0 * (t - 1.0))
+    }
+}
@@ -0,0 +1,103 @@
+//! The library's error type.
+
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Error)]
+pub enum SimError {
+    #[error("{action} {}", path.display())]
+    Io {
+        action: &'static str,
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// An image file that couldn't be opened or written.
+    #[error("{action} {}", path.display())]
+    Image {
+        action: &'static str,
+        path: PathBuf,
+        #[source]
+        source: image::ImageError,
+    },
+    /// Text or bytes that didn't parse as what they were meant to hold.
+    #[error("parsing {what}")]
+    Decode {
+        what: String,
+        #[source]
+        source: BoxError,
+    },
+    #[error("encoding {what}")]
+    Encode {
+        what: &'static str,
+        #[source]
+        source: BoxError,
+    },
+    /// A pixel buffer whose length doesn't fit the size it is meant to be.
+    #[error("a {width}x{height} buffer needs {expected} bytes but has {actual}")]
+    BufferDims {
+        width: u32,
+        height: u32,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("state was saved at {}x{} but the canvas is {}x{}", saved.0, saved.1, canvas.0, canvas.1)]
+    CanvasSize { saved: (u32, u32), canvas: (u32, u32) },
+    /// Input that parsed but can't be used, e.g. a param name that
+    /// doesn't exist.
+    #[error("{0}")]
+    Invalid(String),
+    #[error("no GPU adapter available")]
+    NoAdapter,
+    #[error("requesting GPU device")]
+    Device(#[from] pixels::wgpu::RequestDeviceError),
+}
+
+impl SimError {
+    /// For `map_err` on an I/O result: `action` is e.g. "reading".
+    pub(crate) fn io(action: &'static str, path: impl AsRef<Path>) -> impl FnOnce(std::io::Error) -> SimError {
+        let path = path.as_ref().to_path_buf();
+        move |source| SimError::Io { action, path, source }
+    }
+
+    pub(crate) fn image(action: &'static str, path: impl AsRef<Path>) -> impl FnOnce(image::ImageError) -> SimError {
+        let path = path.as_ref().to_path_buf();
+        move |source| SimError::Image { action, path, source }
+    }
+
+    pub(crate) fn decode<E>(what: impl Into<String>) -> impl FnOnce(E) -> SimError
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        let what = what.into();
+        move |source| SimError::Decode {
+            what,
+            source: Box::new(source),
+        }
+    }
+
+    pub(crate) fn encode<E>(what: &'static str) -> impl FnOnce(E) -> SimError
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        move |source| SimError::Encode {
+            what,
+            source: Box::new(source),
+        }
+    }
+
+    /// Names the file an error from parsing its contents came from.
+    pub(crate) fn in_file(self, path: &str) -> SimError {
+        match self {
+            SimError::Decode { source, .. } => SimError::Decode {
+                what: path.to_string(),
+                source,
+            },
+            SimError::Invalid(message) => SimError::Invalid(format!("{}: {}", path, message)),
+            other => other,
+        }
+    }
+}
@@ -0,0 +1,219 @@
+//! Noise generators backing the flow field and the direction lookups on them.
+
+use std::cell::RefCell;
+
+use glam::Vec2;
+use noise::{NoiseFn, OpenSimplex, Perlin, Worley};
+
+use crate::error::SimError;
+use crate::params::{DomainWarp, FieldMode, NoiseKind, Params};
+
+/// Concrete noise generator backing the flow field, selected by `NoiseKind`.
+pub enum FlowNoise {
+    Perlin(Perlin),
+    OpenSimplex(OpenSimplex),
+    /// Just the seed: `Worley` holds its distance function in an `Rc`, so it
+    /// can't be shared with the stepping threads, and each builds its own.
+    Worley(u32),
+}
+
+thread_local! {
+    static WORLEY: RefCell<Option<(u32, Worley)>> = const { RefCell::new(None) };
+}
+
+impl FlowNoise {
+    pub fn new(kind: NoiseKind, seed: u32) -> Self {
+        match kind {
+            NoiseKind::Perlin => FlowNoise::Perlin(Perlin::new(seed)),
+            NoiseKind::OpenSimplex => FlowNoise::OpenSimplex(OpenSimplex::new(seed)),
+            NoiseKind::Worley => FlowNoise::Worley(seed),
+        }
+    }
+}
+
+impl<const N: usize> NoiseFn<f64, N> for FlowNoise
+where
+    Perlin: NoiseFn<f64, N>,
+    OpenSimplex: NoiseFn<f64, N>,
+    Worley: NoiseFn<f64, N>,
+{
+    fn get(&self, point: [f64; N]) -> f64 {
+        match self {
+            FlowNoise::Perlin(n) => n.get(point),
+            FlowNoise::OpenSimplex(n) => n.get(point),
+            FlowNoise::Worley(seed) => WORLEY.with(|cached| {
+                let mut cached = cached.borrow_mut();
+                match &*cached {
+                    Some((s, worley)) if s == seed => worley.get(point),
+                    _ => cached.insert((*seed, Worley::new(*seed))).1.get(point),
+                }
+            }),
+        }
+    }
+}
+
+/// Maps `p` through `warp` around the center of a `size` frame. Angles are
+/// scaled by half the shorter side so they span about as many noise cells
+/// as the radius does.
+pub fn warp_domain(warp: DomainWarp, p: Vec2, size: Vec2) -> Vec2 {
+    let center = size * 0.5;
+    let radius = center.min_element().max(1.0);
+    let d = p - center;
+    match warp {
+        DomainWarp::None => p,
+        DomainWarp::Polar => {
+            // Mirrored so there's no seam where atan2 wraps around
+            let angle = d.y.atan2(d.x).abs();
+            center + Vec2::new(d.length(), angle * radius)
+        }
+        DomainWarp::Swirl => {
+            let turn = d.length() / radius * std::f32::consts::PI;
+            center + Vec2::from_angle(turn).rotate(d)
+        }
+    }
+}
+
+/// Unit flow direction at `p`, per `params.field_mode`.
+///
+/// `t` is how far through the frame the lookup happens, in 0..1: the z slice
+/// (and each layer's) is advanced by that fraction of its `z_step`, so the
+/// field moves between substeps instead of jumping once per frame. On a
+/// loop `Simulator::step` sets `z_step` to the sweep's move to next frame.
+/// `size` is the frame, which the field repeats over with `params.tileable`.
+pub fn noise_dir(noise: &FlowNoise, params: &Params, p: Vec2, t: f32, size: Vec2) -> Vec2 {
+    match params.field_mode {
+        FieldMode::Angle => {
+            let angle = noise_angle(noise, params, p, t, size);
+            Vec2::new(angle.cos(), angle.sin())
+        }
+        FieldMode::Curl => Vec2::from_angle(params.field_rotation).rotate(curl_dir(noise, params, p, t, size)),
+    }
+}
+
+/// Maps the fBm value at `p` to an angle, turned by `params.field_rotation`.
+pub fn noise_angle(noise: &FlowNoise, params: &Params, p: Vec2, t: f32, size: Vec2) -> f32 {
+    noise_value(noise, params, p, t, size) * std::f32::consts::TAU + params.field_rotation
+}
+
+/// Normalized 2D curl `(dpsi/dy, -dpsi/dx)` of the fBm potential, by central
+/// differences.
+pub fn curl_dir(noise: &FlowNoise, params: &Params, p: Vec2, t: f32, size: Vec2) -> Vec2 {
+    let eps = 1.0;
+    let dx = noise_value(noise, params, p + Vec2::new(eps, 0.0), t, size)
+        - noise_value(noise, params, p - Vec2::new(eps, 0.0), t, size);
+    let dy = noise_value(noise, params, p + Vec2::new(0.0, eps), t, size)
+        - noise_value(noise, params, p - Vec2::new(0.0, eps), t, size);
+    Vec2::new(dy, -dx).normalize_or_zero()
+}
+
+/// The base fBm field plus each of `params.layers` scaled by its weight,
+/// `t` of the way from this frame's z slices to the next.
+pub fn noise_value(noise: &FlowNoise, params: &Params, p: Vec2, t: f32, size: Vec2) -> f32 {
+    let p = p * params.zoom + params.offset;
+    let tile = params.tileable.then_some(size);
+    let scale = Vec2::new(params.scale_x, params.scale_y);
+    let mut value = fbm(noise, params, p, scale, params.z + t * params.z_step, tile);
+    for layer in &params.layers {
+        let scale = Vec2::splat(layer.scale);
+        value += layer.weight * fbm(noise, params, p, scale, layer.z + t * layer.z_step, tile);
+    }
+    value
+}
+
+/// Sums `params.octaves` octaves of the noise starting at the per-axis
+/// frequency `scale`, normalized by the total amplitude. With
+/// `params.ridged` each octave is folded to `1 - |n|` first, so the result
+/// is in 0..1 and peaks along the noise's zero crossings. With a `tile`
+/// the result repeats every `tile` across and down.
+fn fbm(noise: &FlowNoise, params: &Params, p: Vec2, scale: Vec2, z: f32, tile: Option<Vec2>) -> f32 {
+    let mut freq = scale;
+    let mut amp = 1.0;
+    let mut sum = 0.0;
+    let mut norm = 0.0;
+    for _ in 0..params.octaves.max(1) {
+        let mut n = match tile {
+            Some(tile) => noise.get(torus_point(p, tile, freq, z)) as f32,
+            None => {
+                let q = p * freq;
+                noise.get([q.x as f64, q.y as f64, z as f64]) as f32
+            }
+        };
+        if params.ridged {
+            n = 1.0 - n.abs();
+        }
+        sum += n * amp;
+        norm += amp;
+        freq *= params.lacunarity;
+        amp *= params.persistence;
+    }
+    sum / norm
+}
+
+/// Where `p` lands on a torus in 4D noise space that goes once round each
+/// of its circles per `tile`, sized so the noise still changes at `freq`
+/// per pixel. z slides the whole torus along, keeping it periodic.
+fn torus_point(p: Vec2, tile: Vec2, freq: Vec2, z: f32) -> [f64; 4] {
+    let angle = (p / tile).as_dvec2() * std::f64::consts::TAU;
+    let radius = (tile * freq).as_dvec2() / std::f64::consts::TAU;
+    let z = z as f64;
+    [
+        angle.x.cos() * radius.x + z,
+        angle.x.sin() * radius.x + z,
+        angle.y.cos() * radius.y + z,
+        angle.y.sin() * radius.y + z,
+    ]
+}
+
+/// Direction field taken from the intensity gradient of a grayscale image,
+/// used in place of noise when given with `--field-image`.
+pub struct ImageField {
+    width: u32,
+    height: u32,
+    /// Unit gradient per pixel (zero where the image is flat).
+    dirs: Vec<Vec2>,
+}
+
+impl ImageField {
+    /// Loads `path` as grayscale and computes a Sobel gradient at every
+    /// pixel. With `contour`, directions are rotated 90 degrees so particles
+    /// follow lines of constant intensity instead of crossing them.
+    pub fn load(path: &str, contour: bool) -> Result<Self, SimError> {
+        let img = image::open(path).map_err(SimError::image("opening", path))?.into_luma8();
+        let (width, height) = img.dimensions();
+        if width == 0 || height == 0 {
+            return Err(SimError::Invalid(format!("{} has no pixels", path)));
+        }
+        let at = |x: i64, y: i64| {
+            let x = x.clamp(0, width as i64 - 1) as u32;
+            let y = y.clamp(0, height as i64 - 1) as u32;
+            img.get_pixel(x, y).0[0] as f32 / 255.0
+        };
+
+        let mut dirs = Vec::with_capacity((width * height) as usize);
+        for y in 0..height as i64 {
+            for x in 0..width as i64 {
+                let gx = (at(x + 1, y - 1) + 2.0 * at(x + 1, y) + at(x + 1, y + 1))
+                    - (at(x - 1, y - 1) + 2.0 * at(x - 1, y) + at(x - 1, y + 1));
+                let gy = (at(x - 1, y + 1) + 2.0 * at(x, y + 1) + at(x + 1, y + 1))
+                    - (at(x - 1, y - 1) + 2.0 * at(x, y - 1) + at(x + 1, y - 1));
+                let g = Vec2::new(gx, gy).normalize_or_zero();
+                dirs.push(if contour { g.perp() } else { g });
+            }
+        }
+        Ok(Self { width, height, dirs })
+    }
+
+    /// Bilinearly samples the field at canvas position `p`, stretching the
+    /// image over a `canvas_w` x `canvas_h` canvas.
+    pub fn dir(&self, p: Vec2, canvas_w: u32, canvas_h: u32) -> Vec2 {
+        let fx = (p.x / canvas_w as f32 * self.width as f32 - 0.5).clamp(0.0, (self.width - 1) as f32);
+        let fy = (p.y / canvas_h as f32 * self.height as f32 - 0.5).clamp(0.0, (self.height - 1) as f32);
+        let (x0, y0) = (fx as u32, fy as u32);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (tx, ty) = (fx - x0 as f32, fy - y0 as f32);
+        let d = |x: u32, y: u32| self.dirs[(y * self.width + x) as usize];
+        let top = d(x0, y0).lerp(d(x1, y0), tx);
+        let bottom = d(x0, y1).lerp(d(x1, y1), tx);
+        top.lerp(bottom, ty).normalize_or_zero()
+    }
+}
@@ -0,0 +1,114 @@
+//! Tiny 5x7 bitmap font for drawing overlay text straight into an RGBA8 frame.
+
+pub const GLYPH_W: u32 = 5;
+pub const GLYPH_H: u32 = 7;
+
+/// Rows of a glyph, top to bottom; bit 4 is the leftmost column. Lowercase
+/// letters map to uppercase and anything unknown draws as blank.
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110],
+        '1' => [0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+        '2' => [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111],
+        '3' => [0b11110, 0b00001, 0b00001, 0b01110, 0b00001, 0b00001, 0b11110],
+        '4' => [0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010],
+        '5' => [0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110],
+        '6' => [0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110],
+        '7' => [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000],
+        '8' => [0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110],
+        '9' => [0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100],
+        'A' => [0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+        'B' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110],
+        'C' => [0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110],
+        'D' => [0b11100, 0b10010, 0b10001, 0b10001, 0b10001, 0b10010, 0b11100],
+        'E' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111],
+        'F' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000],
+        'G' => [0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111],
+        'H' => [0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+        'I' => [0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+        'J' => [0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100],
+        'K' => [0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001],
+        'L' => [0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111],
+        'M' => [0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001],
+        'N' => [0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001],
+        'O' => [0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+        'P' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000],
+        'Q' => [0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101],
+        'R' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001],
+        'S' => [0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110],
+        'T' => [0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100],
+        'U' => [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+        'V' => [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100],
+        'W' => [0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010],
+        'X' => [0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001],
+        'Y' => [0b10001, 0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100],
+        'Z' => [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111],
+        '.' => [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b01100],
+        ',' => [0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b00100, 0b01000],
+        ':' => [0b00000, 0b01100, 0b01100, 0b00000, 0b01100, 0b01100, 0b00000],
+        '-' => [0b00000, 0b00000, 0b00000, 0b11111, 0b00000, 0b00000, 0b00000],
+        '_' => [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b11111],
+        '/' => [0b00001, 0b00010, 0b00010, 0b00100, 0b01000, 0b01000, 0b10000],
+        _ => [0; 7],
+    }
+}
+
+/// Horizontal advance per character at the given pixel `scale`.
+pub fn advance(scale: u32) -> u32 {
+    (GLYPH_W + 1) * scale
+}
+
+/// Draws `text` with its top-left corner at (x, y), each font pixel a
+/// `scale` x `scale` block. Overwrites rather than blends; clipped to the frame.
+#[allow(clippy::too_many_arguments)]
+pub fn draw_text(
+    frame: &mut [u8],
+    width: u32,
+    height: u32,
+    x: u32,
+    y: u32,
+    scale: u32,
+    text: &str,
+    color: (u8, u8, u8),
+) {
+    for (ci, c) in text.chars().enumerate() {
+        let gx = x + ci as u32 * advance(scale);
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..GLYPH_W {
+                if bits & (1 << (GLYPH_W - 1 - col)) == 0 {
+                    continue;
+                }
+                let px = gx + col * scale;
+                let py = y + row as u32 * scale;
+                fill_rect(frame, width, height, px, py, scale, scale, color);
+            }
+        }
+    }
+}
+
+/// Multiplies every pixel in the rectangle by `factor`, e.g. to make a dark
+/// backing panel for text.
+#[allow(clippy::too_many_arguments)]
+pub fn darken_rect(frame: &mut [u8], width: u32, height: u32, x: u32, y: u32, w: u32, h: u32, factor: f32) {
+    for py in y..(y + h).min(height) {
+        for px in x..(x + w).min(width) {
+            let idx = ((py * width + px) * 4) as usize;
+            for c in &mut frame[idx..idx + 3] {
+                *c = (*c as f32 * factor) as u8;
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn fill_rect(frame: &mut [u8], width: u32, height: u32, x: u32, y: u32, w: u32, h: u32, color: (u8, u8, u8)) {
+    for py in y..(y + h).min(height) {
+        for px in x..(x + w).min(width) {
+            let idx = ((py * width + px) * 4) as usize;
+            frame[idx] = color.0;
+            frame[idx + 1] = color.1;
+            frame[idx + 2] = color.2;
+            frame[idx + 3] = 255;
+        }
+    }
+}
@@ -0,0 +1,504 @@
+//! Optional wgpu compute path for [`Simulator::step`](crate::Simulator::step),
+//! selected with `--gpu`.
+//!
+//! Each frame the particles are uploaded to a storage buffer, one shader
+//! invocation advances each of them through every substep, and the segments
+//! they trace are rasterized with atomics into a per-pixel accumulator that
+//! `render_into` later merges into the frame. The shader reimplements
+//! improved Perlin noise over its own permutation table, so the field is
+//! similar to the CPU `noise` crate's but not bit-identical.
+
+use bytemuck::{Pod, Zeroable};
+use glam::Vec2;
+use pixels::wgpu;
+use pixels::wgpu::util::DeviceExt;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+use crate::error::SimError;
+use crate::palette::Stop;
+use crate::params::{AgeEasing, BlendMode, BoundaryMode, ColorMode, DomainWarp, FieldMode, Integrator, NoiseKind, Params, ScalarSource, SplatMode};
+use crate::Particle;
+
+const WORKGROUP_SIZE: u32 = 64;
+
+/// Particle layout shared with `gpu.wgsl`, filling the 32-byte array stride
+/// WGSL gives it.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct GpuParticle {
+    pos: [f32; 2],
+    vel: [f32; 2],
+    age: u32,
+    alive: u32,
+    hue_offset: f32,
+    mass: f32,
+}
+
+// SAFETY: plain `repr(C)` data with no padding and no invalid bit patterns
+unsafe impl Zeroable for GpuParticle {}
+unsafe impl Pod for GpuParticle {}
+
+/// Uniform block shared with `gpu.wgsl`; every field is four bytes and the
+/// total is padded to a multiple of 16.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct GpuParams {
+    width: u32,
+    height: u32,
+    count: u32,
+    steps: u32,
+    scale_x: f32,
+    z: f32,
+    force: f32,
+    friction: f32,
+    lacunarity: f32,
+    persistence: f32,
+    octaves: u32,
+    layer_count: u32,
+    color_mode: u32,
+    palette_source: u32,
+    palette_len: u32,
+    field_mode: u32,
+    boundary: u32,
+    restitution: f32,
+    max_age: u32,
+    blend_max: u32,
+    attract: u32,
+    attract_x: f32,
+    attract_y: f32,
+    attract_strength: f32,
+    attract_radius: f32,
+    hue_shift: f32,
+    speed_ref: f32,
+    z_step: f32,
+    wind_x: f32,
+    wind_y: f32,
+    sat: f32,
+    val_min: f32,
+    val_max: f32,
+    max_speed: f32,
+    domain_warp: u32,
+    scale_y: f32,
+    ridged: u32,
+    dt: f32,
+    age_easing: u32,
+    field_rotation: f32,
+}
+
+// SAFETY: as above
+unsafe impl Zeroable for GpuParams {}
+unsafe impl Pod for GpuParams {}
+
+/// Buffers sized for a particular particle capacity and canvas.
+struct Buffers {
+    capacity: usize,
+    width: u32,
+    height: u32,
+    particles: wgpu::Buffer,
+    particles_readback: wgpu::Buffer,
+    /// `width * height * 3` channel sums (or maxima) plus a segment counter.
+    accum: wgpu::Buffer,
+    accum_readback: wgpu::Buffer,
+}
+
+impl Buffers {
+    fn accum_len(&self) -> u64 {
+        self.width as u64 * self.height as u64 * 3 + 1
+    }
+}
+
+pub struct GpuStepper {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+    layout: wgpu::BindGroupLayout,
+    perm: wgpu::Buffer,
+    buffers: Option<Buffers>,
+    /// Whether the accumulator has been written since the last `composite`.
+    pending: bool,
+}
+
+impl GpuStepper {
+    /// Opens a headless device on the first available adapter and builds the
+    /// compute pipeline.
+    pub fn new(noise_seed: u32) -> Result<Self, SimError> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            force_fallback_adapter: false,
+            compatible_surface: None,
+        }))
+        .ok_or(SimError::NoAdapter)?;
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("flow-field compute"),
+                features: wgpu::Features::empty(),
+                limits: adapter.limits(),
+            },
+            None,
+        ))?;
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("gpu.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("gpu.wgsl").into()),
+        });
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("flow-field bindings"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage(1, false),
+                storage(2, false),
+                storage(3, true),
+                storage(4, true),
+                storage(5, true),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("flow-field pipeline layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("flow-field step"),
+            layout: Some(&pipeline_layout),
+            module: &module,
+            entry_point: "step",
+        });
+        let perm = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("permutation table"),
+            contents: bytemuck::cast_slice(&permutation(noise_seed)),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+
+        Ok(Self {
+            device,
+            queue,
+            pipeline,
+            layout,
+            perm,
+            buffers: None,
+            pending: false,
+        })
+    }
+
+    /// Whether the shader implements everything `params` asks for. The
+    /// simulator steps on the CPU for any frame where this is false.
+    pub fn supports(params: &Params) -> bool {
+        params.noise_kind == NoiseKind::Perlin
+            && !params.antialiased
+            && params.thickness == 0.0
+            && params.density_compensation == 0.0
+            && params.symmetry <= 1
+            && !params.smooth_trails
+            && !matches!(params.blend_mode, BlendMode::AlphaOver)
+            && params.attractors.is_empty()
+            && params.stroke_alpha >= 1.0
+            && params.integrator == Integrator::Euler
+            && params.color_smoothing == 0.0
+            && !params.tileable
+            && params.splat_mode == SplatMode::Lines
+            && params.zoom == 1.0
+            && params.offset == Vec2::ZERO
+    }
+
+    /// Rebuilds the permutation table for a new noise seed.
+    pub fn set_seed(&mut self, seed: u32) {
+        self.queue.write_buffer(&self.perm, 0, bytemuck::cast_slice(&permutation(seed)));
+    }
+
+    /// Advances `particles` by `params.steps_per_frame` substeps of `dt`
+    /// each on the GPU, adding the traced segments to the accumulator.
+    #[allow(clippy::too_many_arguments)]
+    pub fn step(
+        &mut self,
+        particles: &mut [Particle],
+        params: &Params,
+        dt: f32,
+        palette: &[Stop],
+        attractor: Option<Vec2>,
+        width: u32,
+        height: u32,
+    ) {
+        if particles.is_empty() {
+            return;
+        }
+        self.ensure_buffers(particles.len(), width, height);
+        let buffers = self.buffers.as_ref().expect("buffers were just allocated");
+
+        let upload: Vec<GpuParticle> = particles
+            .iter()
+            .map(|p| GpuParticle {
+                pos: p.pos.to_array(),
+                vel: p.vel.to_array(),
+                age: p.age,
+                alive: p.alive as u32,
+                hue_offset: p.hue_offset,
+                mass: p.mass,
+            })
+            .collect();
+        self.queue.write_buffer(&buffers.particles, 0, bytemuck::cast_slice(&upload));
+
+        let target = attractor.unwrap_or(Vec2::ZERO);
+        let uniforms = GpuParams {
+            width,
+            height,
+            count: particles.len() as u32,
+            steps: params.steps_per_frame as u32,
+            scale_x: params.scale_x,
+            scale_y: params.scale_y,
+            z: params.z,
+            z_step: params.z_step,
+            force: params.force,
+            wind_x: params.wind.x,
+            wind_y: params.wind.y,
+            friction: params.friction,
+            lacunarity: params.lacunarity,
+            persistence: params.persistence,
+            octaves: params.octaves,
+            layer_count: params.layers.len() as u32,
+            color_mode: match params.color_mode {
+                ColorMode::Direction => 0,
+                ColorMode::Age => 1,
+                ColorMode::Curl => 2,
+                // Given the duotone as its palette
+                ColorMode::Palette | ColorMode::Duotone | ColorMode::Divergence => 3,
+                ColorMode::Speed => 4,
+            },
+            palette_source: match (params.color_mode, params.palette_source) {
+                (ColorMode::Divergence, _) | (_, ScalarSource::Divergence) => 4,
+                (_, ScalarSource::Direction) => 0,
+                (_, ScalarSource::Age) => 1,
+                (_, ScalarSource::Curl) => 2,
+                (_, ScalarSource::Speed) => 3,
+            },
+            palette_len: palette.len() as u32,
+            field_mode: match params.field_mode {
+                FieldMode::Angle => 0,
+                FieldMode::Curl => 1,
+            },
+            boundary: match params.boundary {
+                BoundaryMode::Kill => 0,
+                BoundaryMode::Wrap => 1,
+                BoundaryMode::Bounce => 2,
+            },
+            restitution: params.restitution,
+            max_age: params.max_age,
+            blend_max: matches!(params.blend_mode, BlendMode::Max) as u32,
+            attract: attractor.is_some() as u32,
+            attract_x: target.x,
+            attract_y: target.y,
+            attract_strength: params.attract_strength,
+            attract_radius: params.attract_radius,
+            hue_shift: params.hue_shift,
+            speed_ref: params.speed_ref,
+            sat: params.sat,
+            val_min: params.val_min,
+            val_max: params.val_max,
+            max_speed: params.max_speed,
+            domain_warp: match params.domain_warp {
+                DomainWarp::None => 0,
+                DomainWarp::Polar => 1,
+                DomainWarp::Swirl => 2,
+            },
+            ridged: params.ridged as u32,
+            dt,
+            age_easing: match params.age_easing {
+                AgeEasing::Linear => 0,
+                AgeEasing::InOut => 1,
+                AgeEasing::Exponential => 2,
+            },
+            field_rotation: params.field_rotation,
+        };
+        // Storage bindings can't be empty, so both get at least one entry
+        let mut layers: Vec<[f32; 4]> = params.layers.iter().map(|l| [l.scale, l.z, l.weight, l.z_step]).collect();
+        layers.push([0.0; 4]);
+        let mut stops: Vec<[f32; 4]> = palette
+            .iter()
+            .map(|&(pos, (r, g, b))| [pos, r as f32, g as f32, b as f32])
+            .collect();
+        stops.push([0.0; 4]);
+
+        let uniform_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("step params"),
+            contents: bytemuck::bytes_of(&uniforms),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let layer_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("noise layers"),
+            contents: bytemuck::cast_slice(&layers),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let palette_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("palette stops"),
+            contents: bytemuck::cast_slice(&stops),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("flow-field bind group"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: buffers.particles.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: buffers.accum.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.perm.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: layer_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: palette_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let particle_bytes = std::mem::size_of_val(upload.as_slice()) as u64;
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("flow-field step"),
+        });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("advance particles"),
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups((particles.len() as u32).div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&buffers.particles, 0, &buffers.particles_readback, 0, particle_bytes);
+        self.queue.submit(Some(encoder.finish()));
+        self.pending = true;
+
+        let readback = read_buffer(&self.device, &buffers.particles_readback, particle_bytes);
+        let advanced: &[GpuParticle] = bytemuck::cast_slice(&readback);
+        for (p, g) in particles.iter_mut().zip(advanced) {
+            p.pos = Vec2::from_array(g.pos);
+            p.vel = Vec2::from_array(g.vel);
+            p.age = g.age;
+            p.alive = g.alive != 0;
+        }
+    }
+
+    /// Merges the accumulated segments into `frame` per `blend_mode`, clears
+    /// the accumulator and returns the number of segments it held.
+    pub fn composite(&mut self, frame: &mut [u8], width: u32, height: u32, blend_mode: BlendMode) -> usize {
+        if !self.pending {
+            return 0;
+        }
+        self.pending = false;
+        let Some(buffers) = &self.buffers else {
+            return 0;
+        };
+        // The canvas changed size since the step; the accumulated trails no
+        // longer line up, so drop them
+        let size_matches = buffers.width == width && buffers.height == height;
+
+        let bytes = buffers.accum_len() * 4;
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("flow-field readback"),
+        });
+        encoder.copy_buffer_to_buffer(&buffers.accum, 0, &buffers.accum_readback, 0, bytes);
+        encoder.clear_buffer(&buffers.accum, 0, None);
+        self.queue.submit(Some(encoder.finish()));
+        let readback = read_buffer(&self.device, &buffers.accum_readback, bytes);
+        let accum: &[u32] = bytemuck::cast_slice(&readback);
+        let (channels, counter) = accum.split_at(accum.len() - 1);
+        if !size_matches {
+            return 0;
+        }
+
+        for (px, sum) in frame.chunks_exact_mut(4).zip(channels.chunks_exact(3)) {
+            for (c, &s) in px.iter_mut().zip(sum) {
+                let s = s.min(255) as u8;
+                *c = match blend_mode {
+                    BlendMode::Max => (*c).max(s),
+                    _ => c.saturating_add(s),
+                };
+            }
+        }
+        counter[0] as usize
+    }
+
+    fn ensure_buffers(&mut self, count: usize, width: u32, height: u32) {
+        if let Some(b) = &self.buffers {
+            if b.capacity >= count && b.width == width && b.height == height {
+                return;
+            }
+        }
+        // Grow geometrically so a slowly rising particle count doesn't
+        // reallocate every frame
+        let capacity = count.next_power_of_two();
+        let particle_bytes = (capacity * std::mem::size_of::<GpuParticle>()) as u64;
+        let accum_bytes = (width as u64 * height as u64 * 3 + 1) * 4;
+        let buffer = |label, size, usage| {
+            self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+        use wgpu::BufferUsages as U;
+        self.buffers = Some(Buffers {
+            capacity,
+            width,
+            height,
+            particles: buffer("particles", particle_bytes, U::STORAGE | U::COPY_DST | U::COPY_SRC),
+            particles_readback: buffer("particles readback", particle_bytes, U::MAP_READ | U::COPY_DST),
+            accum: buffer("accumulator", accum_bytes, U::STORAGE | U::COPY_SRC | U::COPY_DST),
+            accum_readback: buffer("accumulator readback", accum_bytes, U::MAP_READ | U::COPY_DST),
+        });
+        self.pending = false;
+    }
+}
+
+/// Blocks until the first `size` bytes of `buffer` can be mapped and returns
+/// a copy of them.
+fn read_buffer(device: &wgpu::Device, buffer: &wgpu::Buffer, size: u64) -> Vec<u8> {
+    let slice = buffer.slice(..size);
+    slice.map_async(wgpu::MapMode::Read, |result| {
+        result.expect("mapping GPU readback buffer");
+    });
+    device.poll(wgpu::Maintain::Wait);
+    let data = slice.get_mapped_range().to_vec();
+    buffer.unmap();
+    data
+}
+
+/// Ken Perlin's doubled 256-entry permutation, shuffled by `seed`.
+fn permutation(seed: u32) -> Vec<u32> {
+    let mut table: Vec<u32> = (0..256).collect();
+    table.shuffle(&mut StdRng::seed_from_u64(seed as u64));
+    table.extend_from_within(..);
+    table
+}
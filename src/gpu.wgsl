@@ -0,0 +1,429 @@
+// Compute path for `Simulator::step`: one invocation advances one particle
+// through every substep and rasterizes its segments into an accumulation
+// buffer. Mirrors the CPU code in lib.rs; keep the two in step.
+
+struct SimParams {
+    width: u32,
+    height: u32,
+    count: u32,
+    steps: u32,
+    scale_x: f32,
+    z: f32,
+    force: f32,
+    friction: f32,
+    lacunarity: f32,
+    persistence: f32,
+    octaves: u32,
+    layer_count: u32,
+    color_mode: u32,
+    palette_source: u32,
+    palette_len: u32,
+    field_mode: u32,
+    boundary: u32,
+    restitution: f32,
+    max_age: u32,
+    blend_max: u32,
+    attract: u32,
+    attract_x: f32,
+    attract_y: f32,
+    attract_strength: f32,
+    attract_radius: f32,
+    hue_shift: f32,
+    speed_ref: f32,
+    z_step: f32,
+    wind_x: f32,
+    wind_y: f32,
+    sat: f32,
+    val_min: f32,
+    val_max: f32,
+    max_speed: f32,
+    domain_warp: u32,
+    scale_y: f32,
+    ridged: u32,
+    dt: f32,
+    age_easing: u32,
+    field_rotation: f32,
+}
+
+struct Particle {
+    pos: vec2<f32>,
+    vel: vec2<f32>,
+    age: u32,
+    alive: u32,
+    hue_offset: f32,
+    mass: f32,
+}
+
+@group(0) @binding(0) var<uniform> params: SimParams;
+@group(0) @binding(1) var<storage, read_write> particles: array<Particle>;
+// Per-pixel RGB sums (or maxima), then one trailing segment counter
+@group(0) @binding(2) var<storage, read_write> accum: array<atomic<u32>>;
+@group(0) @binding(3) var<storage, read> perm: array<u32>;
+// (scale, z, weight, z_step) per extra noise layer
+@group(0) @binding(4) var<storage, read> layers: array<vec4<f32>>;
+// (position, r, g, b) per palette stop, colors in 0..=255
+@group(0) @binding(5) var<storage, read> palette: array<vec4<f32>>;
+
+const PI: f32 = 3.14159265;
+const TAU: f32 = 6.28318531;
+
+fn fade(t: f32) -> f32 {
+    return t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+}
+
+fn grad(hash: u32, x: f32, y: f32, z: f32) -> f32 {
+    let h = hash & 15u;
+    let u = select(y, x, h < 8u);
+    let v = select(select(z, x, h == 12u || h == 14u), y, h < 4u);
+    return select(-u, u, (h & 1u) == 0u) + select(-v, v, (h & 2u) == 0u);
+}
+
+// Improved Perlin noise over the 512-entry permutation table in `perm`.
+fn perlin(p: vec3<f32>) -> f32 {
+    let fl = floor(p);
+    let xi = u32(i32(fl.x) & 255);
+    let yi = u32(i32(fl.y) & 255);
+    let zi = u32(i32(fl.z) & 255);
+    let f = p - fl;
+    let u = fade(f.x);
+    let v = fade(f.y);
+    let w = fade(f.z);
+
+    let a = perm[xi] + yi;
+    let aa = perm[a] + zi;
+    let ab = perm[a + 1u] + zi;
+    let b = perm[xi + 1u] + yi;
+    let ba = perm[b] + zi;
+    let bb = perm[b + 1u] + zi;
+
+    let x1 = mix(grad(perm[aa], f.x, f.y, f.z), grad(perm[ba], f.x - 1.0, f.y, f.z), u);
+    let x2 = mix(grad(perm[ab], f.x, f.y - 1.0, f.z), grad(perm[bb], f.x - 1.0, f.y - 1.0, f.z), u);
+    let y1 = mix(x1, x2, v);
+    let x3 = mix(grad(perm[aa + 1u], f.x, f.y, f.z - 1.0), grad(perm[ba + 1u], f.x - 1.0, f.y, f.z - 1.0), u);
+    let x4 = mix(grad(perm[ab + 1u], f.x, f.y - 1.0, f.z - 1.0), grad(perm[bb + 1u], f.x - 1.0, f.y - 1.0, f.z - 1.0), u);
+    let y2 = mix(x3, x4, v);
+    return mix(y1, y2, w);
+}
+
+fn fbm(p: vec2<f32>, scale: vec2<f32>, z: f32) -> f32 {
+    var freq = scale;
+    var amp = 1.0;
+    var sum = 0.0;
+    var norm = 0.0;
+    for (var i = 0u; i < max(params.octaves, 1u); i++) {
+        var n = perlin(vec3<f32>(p * freq, z));
+        if params.ridged != 0u {
+            n = 1.0 - abs(n);
+        }
+        sum += n * amp;
+        norm += amp;
+        freq *= params.lacunarity;
+        amp *= params.persistence;
+    }
+    return sum / norm;
+}
+
+// Fraction of the frame the current substep is through, for the z slices
+var<private> frame_t: f32;
+
+fn noise_value(p: vec2<f32>) -> f32 {
+    var value = fbm(p, vec2<f32>(params.scale_x, params.scale_y), params.z + frame_t * params.z_step);
+    for (var i = 0u; i < params.layer_count; i++) {
+        let layer = layers[i];
+        value += layer.z * fbm(p, vec2<f32>(layer.x), layer.y + frame_t * layer.w);
+    }
+    return value;
+}
+
+fn noise_angle(p: vec2<f32>) -> f32 {
+    return noise_value(p) * TAU + params.field_rotation;
+}
+
+fn normalize_or_zero(v: vec2<f32>) -> vec2<f32> {
+    let len = length(v);
+    if len > 0.0 {
+        return v / len;
+    }
+    return vec2<f32>(0.0);
+}
+
+fn noise_dir(p: vec2<f32>) -> vec2<f32> {
+    if params.field_mode == 1u {
+        let dx = noise_value(p + vec2<f32>(1.0, 0.0)) - noise_value(p - vec2<f32>(1.0, 0.0));
+        let dy = noise_value(p + vec2<f32>(0.0, 1.0)) - noise_value(p - vec2<f32>(0.0, 1.0));
+        let curl = normalize_or_zero(vec2<f32>(dy, -dx));
+        let c = cos(params.field_rotation);
+        let s = sin(params.field_rotation);
+        return vec2<f32>(c * curl.x - s * curl.y, s * curl.x + c * curl.y);
+    }
+    let angle = noise_angle(p);
+    return vec2<f32>(cos(angle), sin(angle));
+}
+
+// Mirrors warp_domain in field.rs: 0 none, 1 polar, 2 swirl.
+fn warp_domain(p: vec2<f32>) -> vec2<f32> {
+    let center = vec2<f32>(f32(params.width), f32(params.height)) * 0.5;
+    let radius = max(min(center.x, center.y), 1.0);
+    let d = p - center;
+    if params.domain_warp == 1u {
+        return center + vec2<f32>(length(d), abs(atan2(d.y, d.x)) * radius);
+    }
+    if params.domain_warp == 2u {
+        let turn = length(d) / radius * PI;
+        let c = cos(turn);
+        let s = sin(turn);
+        return center + vec2<f32>(c * d.x - s * d.y, s * d.x + c * d.y);
+    }
+    return p;
+}
+
+fn attraction(pos: vec2<f32>) -> vec2<f32> {
+    let delta = vec2<f32>(params.attract_x, params.attract_y) - pos;
+    let dist = length(delta);
+    if dist < 1.0 {
+        return vec2<f32>(0.0);
+    }
+    let falloff = params.attract_radius / (params.attract_radius + dist);
+    return delta / dist * params.attract_strength * falloff * falloff;
+}
+
+fn to_byte(c: f32) -> u32 {
+    return u32(round(clamp(c, 0.0, 1.0) * 255.0));
+}
+
+fn hsv_to_rgb(h_in: f32, v_in: f32) -> vec3<u32> {
+    let s = clamp(params.sat, 0.0, 1.0);
+    let v = clamp(v_in, 0.0, 1.0);
+    let h = fract(h_in);
+    let i = i32(floor(h * 6.0));
+    let f = h * 6.0 - f32(i);
+    let p = v * (1.0 - s);
+    let q = v * (1.0 - f * s);
+    let t = v * (1.0 - (1.0 - f) * s);
+    var rgb: vec3<f32>;
+    switch ((i % 6 + 6) % 6) {
+        case 0: { rgb = vec3<f32>(v, t, p); }
+        case 1: { rgb = vec3<f32>(q, v, p); }
+        case 2: { rgb = vec3<f32>(p, v, t); }
+        case 3: { rgb = vec3<f32>(p, q, v); }
+        case 4: { rgb = vec3<f32>(t, p, v); }
+        default: { rgb = vec3<f32>(v, p, q); }
+    }
+    return vec3<u32>(to_byte(rgb.x), to_byte(rgb.y), to_byte(rgb.z));
+}
+
+fn palette_sample(t_in: f32, v_in: f32) -> vec3<u32> {
+    if params.palette_len == 0u {
+        return vec3<u32>(0u);
+    }
+    let t = clamp(t_in, 0.0, 1.0);
+    var rgb = palette[0].yzw;
+    if t > palette[0].x {
+        rgb = palette[params.palette_len - 1u].yzw;
+        for (var i = 0u; i + 1u < params.palette_len; i++) {
+            let s0 = palette[i];
+            let s1 = palette[i + 1u];
+            if t <= s1.x {
+                let span = max(s1.x - s0.x, 1.1920929e-7);
+                let f = clamp((t - s0.x) / span, 0.0, 1.0);
+                rgb = floor(s0.yzw + (s1.yzw - s0.yzw) * f);
+                break;
+            }
+        }
+    }
+    let v = clamp(v_in, 0.0, 1.0);
+    return vec3<u32>(round(rgb * v));
+}
+
+// Returns (t, v) for the given scalar source: 0 direction, 1 age, 2 curl,
+// 3 speed.
+// Mirrors easing::ease
+fn ease_age(t: f32) -> f32 {
+    if params.age_easing == 1u {
+        if t < 0.5 {
+            return 4.0 * t * t * t;
+        }
+        let u = 2.0 - 2.0 * t;
+        return 1.0 - u * u * u * 0.5;
+    }
+    if params.age_easing == 2u {
+        if t <= 0.0 {
+            return 0.0;
+        }
+        return pow(2.0, 10.0 * (t - 1.0));
+    }
+    return t;
+}
+
+fn color_scalar(source: u32, particle: Particle, lookup: vec2<f32>) -> vec2<f32> {
+    let speed = length(particle.vel);
+    if source == 4u {
+        let ex = vec2<f32>(2.0, 0.0);
+        let ey = vec2<f32>(0.0, 2.0);
+        let div = (noise_dir(lookup + ex).x - noise_dir(lookup - ex).x
+            + noise_dir(lookup + ey).y - noise_dir(lookup - ey).y) / 4.0;
+        let rate = sqrt(params.scale_x * params.scale_y) * TAU;
+        let t = 0.5 + 0.5 * clamp(div / max(rate, 1.1920929e-7), -1.0, 1.0);
+        return vec2<f32>(t, clamp(speed * 0.6, 0.2, 1.0));
+    }
+    if source == 3u {
+        return vec2<f32>(clamp(speed / max(params.speed_ref, 1.1920929e-7), 0.0, 1.0), 1.0);
+    }
+    if source == 1u {
+        let hue = ease_age(fract(f32(particle.age) * 0.002));
+        return vec2<f32>(fract(hue + params.hue_shift), clamp(speed * 0.5, 0.1, 1.0));
+    }
+    if source == 2u {
+        let a0 = noise_angle(lookup);
+        let a1 = noise_angle(lookup + vec2<f32>(2.0, 0.0));
+        var da = a1 - a0;
+        while da > PI {
+            da -= TAU;
+        }
+        while da < -PI {
+            da += TAU;
+        }
+        return vec2<f32>(clamp(abs(da) / PI, 0.0, 1.0), clamp(speed * 0.6, 0.2, 1.0));
+    }
+    let hue = fract(atan2(particle.vel.y, particle.vel.x) / TAU);
+    return vec2<f32>(fract(hue + params.hue_shift), clamp(speed * 0.5, 0.1, 1.0));
+}
+
+fn life_taper(age: u32) -> f32 {
+    let remaining = 1.0 - f32(age) / f32(max(params.max_age, 1u));
+    let x = clamp(remaining / 0.2, 0.0, 1.0);
+    return x * x * (3.0 - 2.0 * x);
+}
+
+fn plot(x: i32, y: i32, color: vec3<u32>) {
+    if x < 0 || y < 0 || u32(x) >= params.width || u32(y) >= params.height {
+        return;
+    }
+    let idx = (u32(y) * params.width + u32(x)) * 3u;
+    if params.blend_max == 1u {
+        atomicMax(&accum[idx], color.x);
+        atomicMax(&accum[idx + 1u], color.y);
+        atomicMax(&accum[idx + 2u], color.z);
+    } else {
+        atomicAdd(&accum[idx], color.x);
+        atomicAdd(&accum[idx + 1u], color.y);
+        atomicAdd(&accum[idx + 2u], color.z);
+    }
+}
+
+// Same integer Bresenham walk as raster::draw_segment_bresenham.
+fn draw_segment(p0: vec2<f32>, p1: vec2<f32>, color: vec3<u32>) {
+    var x0 = i32(p0.x);
+    var y0 = i32(p0.y);
+    let x1 = i32(p1.x);
+    let y1 = i32(p1.y);
+    let dx = abs(x1 - x0);
+    let sx = select(-1, 1, x0 < x1);
+    let dy = -abs(y1 - y0);
+    let sy = select(-1, 1, y0 < y1);
+    var err = dx + dy;
+    // Bounded so a runaway particle can't stall the dispatch
+    for (var n = 0; n < 8192; n++) {
+        plot(x0, y0, color);
+        if x0 == x1 && y0 == y1 {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x0 += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y0 += sy;
+        }
+    }
+}
+
+@compute @workgroup_size(64)
+fn step(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if index >= params.count {
+        return;
+    }
+    var particle = particles[index];
+    if particle.alive == 0u {
+        return;
+    }
+
+    let size = vec2<f32>(f32(params.width), f32(params.height));
+    let margin = 10.0;
+    var segments = 0u;
+    for (var s = 0u; s < params.steps; s++) {
+        let prev = particle.pos;
+        frame_t = f32(s) / f32(params.steps);
+        let lookup = warp_domain(prev);
+        if params.attract == 1u {
+            particle.vel += attraction(particle.pos) * params.dt;
+        }
+        particle.vel += (noise_dir(lookup) * params.force / particle.mass + vec2<f32>(params.wind_x, params.wind_y)) * params.dt;
+        particle.vel *= pow(params.friction, params.dt);
+        let speed = length(particle.vel);
+        if speed > params.max_speed {
+            particle.vel *= params.max_speed / speed;
+        }
+        particle.pos += particle.vel * params.dt;
+        particle.age += 1u;
+
+        var source = params.color_mode;
+        if params.color_mode == 3u {
+            source = params.palette_source;
+        } else if params.color_mode == 4u {
+            source = 3u;
+        }
+        let tv = color_scalar(source, particle, lookup);
+        let v = (params.val_min + tv.y * (params.val_max - params.val_min)) * life_taper(particle.age);
+        var color: vec3<u32>;
+        if params.color_mode == 3u {
+            color = palette_sample(tv.x + particle.hue_offset, v);
+        } else if params.color_mode == 4u {
+            color = hsv_to_rgb(tv.x * 0.75 + particle.hue_offset, v);
+        } else {
+            color = hsv_to_rgb(tv.x + particle.hue_offset, v);
+        }
+
+        var visible = true;
+        var died = particle.age >= params.max_age;
+        if params.boundary == 0u {
+            died = died || particle.pos.x < -margin || particle.pos.x > size.x + margin
+                || particle.pos.y < -margin || particle.pos.y > size.y + margin;
+        } else if params.boundary == 1u {
+            let wrapped = particle.pos - size * floor(particle.pos / size);
+            visible = all(wrapped == particle.pos);
+            particle.pos = wrapped;
+        } else {
+            let max_p = size - vec2<f32>(1.0);
+            if particle.pos.x < 0.0 {
+                particle.pos.x = -particle.pos.x;
+                particle.vel.x = -particle.vel.x * params.restitution;
+            } else if particle.pos.x > max_p.x {
+                particle.pos.x = 2.0 * max_p.x - particle.pos.x;
+                particle.vel.x = -particle.vel.x * params.restitution;
+            }
+            if particle.pos.y < 0.0 {
+                particle.pos.y = -particle.pos.y;
+                particle.vel.y = -particle.vel.y * params.restitution;
+            } else if particle.pos.y > max_p.y {
+                particle.pos.y = 2.0 * max_p.y - particle.pos.y;
+                particle.vel.y = -particle.vel.y * params.restitution;
+            }
+            particle.pos = clamp(particle.pos, vec2<f32>(0.0), max_p);
+        }
+
+        if visible {
+            draw_segment(prev, particle.pos, color);
+            segments += 1u;
+        }
+        if died {
+            particle.alive = 0u;
+            break;
+        }
+    }
+    particles[index] = particle;
+    atomicAdd(&accum[params.width * params.height * 3u], segments);
+}
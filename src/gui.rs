@@ -0,0 +1,143 @@
+//! The `--gui` control panel: egui drawn over the frame on the pixels
+//! surface, after the scaling pass.
+
+use egui::{ClippedPrimitive, Context, TexturesDelta};
+use egui_wgpu::renderer::{Renderer, ScreenDescriptor};
+use pixels::{wgpu, Pixels, PixelsContext};
+use rust_color_visuals::Params;
+use winit::event::WindowEvent;
+use winit::event_loop::EventLoopWindowTarget;
+use winit::window::Window;
+
+use crate::keymap::Action;
+use crate::COLOR_MODES;
+
+pub struct Gui {
+    ctx: Context,
+    state: egui_winit::State,
+    screen: ScreenDescriptor,
+    renderer: Renderer,
+    paint_jobs: Vec<ClippedPrimitive>,
+    textures: TexturesDelta,
+}
+
+impl Gui {
+    pub fn new<T>(event_loop: &EventLoopWindowTarget<T>, window: &Window, pixels: &Pixels) -> Self {
+        let size = window.inner_size();
+        let scale_factor = window.scale_factor() as f32;
+        let mut state = egui_winit::State::new(event_loop);
+        state.set_max_texture_side(pixels.device().limits().max_texture_dimension_2d as usize);
+        state.set_pixels_per_point(scale_factor);
+        Self {
+            ctx: Context::default(),
+            state,
+            screen: ScreenDescriptor {
+                size_in_pixels: [size.width, size.height],
+                pixels_per_point: scale_factor,
+            },
+            renderer: Renderer::new(pixels.device(), pixels.render_texture_format(), None, 1),
+            paint_jobs: Vec::new(),
+            textures: TexturesDelta::default(),
+        }
+    }
+
+    /// Passes `event` to egui and says whether the panel took it, so the
+    /// app should ignore it. Keys only count while a widget has focus, so
+    /// Tab and the rest still reach the app otherwise.
+    pub fn on_event(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::Resized(size) if size.width > 0 && size.height > 0 => {
+                self.screen.size_in_pixels = [size.width, size.height];
+            }
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                self.screen.pixels_per_point = *scale_factor as f32;
+            }
+            _ => {}
+        }
+        let response = self.state.on_event(&self.ctx, event);
+        match event {
+            WindowEvent::KeyboardInput { .. } | WindowEvent::ReceivedCharacter(_) => self.ctx.wants_keyboard_input(),
+            _ => response.consumed,
+        }
+    }
+
+    /// Lays out the panel for this frame, editing `params` in place, and
+    /// returns the actions its buttons asked for. `max_spawn` caps the
+    /// spawn count slider.
+    pub fn prepare(&mut self, window: &Window, params: &mut Params, max_spawn: usize) -> Vec<Action> {
+        let input = self.state.take_egui_input(window);
+        let mut actions = Vec::new();
+        let output = self.ctx.run(input, |ctx| panel(ctx, params, max_spawn, &mut actions));
+        self.textures.append(output.textures_delta);
+        self.state.handle_platform_output(window, &self.ctx, output.platform_output);
+        self.paint_jobs = self.ctx.tessellate(output.shapes);
+        actions
+    }
+
+    /// Draws the panel prepared last onto `target`, over what is there.
+    pub fn render(&mut self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView, context: &PixelsContext) {
+        for (id, delta) in &self.textures.set {
+            self.renderer.update_texture(&context.device, &context.queue, *id, delta);
+        }
+        self.renderer
+            .update_buffers(&context.device, &context.queue, encoder, &self.paint_jobs, &self.screen);
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("egui"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            self.renderer.render(&mut pass, &self.paint_jobs, &self.screen);
+        }
+        for id in &std::mem::take(&mut self.textures).free {
+            self.renderer.free_texture(id);
+        }
+    }
+}
+
+/// The sliders, color mode list and buttons, with the same ranges the keys
+/// keep to.
+fn panel(ctx: &Context, params: &mut Params, max_spawn: usize, actions: &mut Vec<Action>) {
+    egui::Window::new("Controls")
+        .anchor(egui::Align2::RIGHT_TOP, [-8.0, 8.0])
+        .resizable(false)
+        .show(ctx, |ui| {
+            let mut scale = params.scale();
+            if ui
+                .add(egui::Slider::new(&mut scale, 0.0005..=0.05).logarithmic(true).text("scale"))
+                .changed()
+            {
+                params.set_scale_by(scale / params.scale());
+            }
+            ui.add(egui::Slider::new(&mut params.force, 0.05..=5.0).logarithmic(true).text("force"));
+            ui.add(egui::Slider::new(&mut params.friction, 0.90..=0.9995).text("friction"));
+            let mut fade = params.fade;
+            if ui.add(egui::Slider::new(&mut fade, 0.0..=0.2).text("fade")).changed() {
+                params.set_fade(fade);
+            }
+            ui.add(egui::Slider::new(&mut params.steps_per_frame, 1..=2000).logarithmic(true).text("steps per frame"));
+            ui.add(egui::Slider::new(&mut params.spawn_count, 0..=max_spawn).logarithmic(true).text("spawn count"));
+            egui::ComboBox::from_label("color mode")
+                .selected_text(format!("{:?}", params.color_mode))
+                .show_ui(ui, |ui| {
+                    for mode in COLOR_MODES {
+                        ui.selectable_value(&mut params.color_mode, mode, format!("{:?}", mode));
+                    }
+                });
+            ui.horizontal(|ui| {
+                let buttons = [("Save", Action::SavePng), ("Reseed", Action::ReseedNoise), ("Reset", Action::Reset)];
+                for (label, action) in buttons {
+                    if ui.button(label).clicked() {
+                        actions.push(action);
+                    }
+                }
+            });
+        });
+}
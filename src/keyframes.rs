@@ -0,0 +1,186 @@
+//! Params keyframed over a headless render, read from JSON.
+
+use serde_json::{Map, Value};
+
+use crate::error::SimError;
+use crate::Params;
+
+/// Full param states at a sorted list of frames, each keyframe's partial
+/// params laid over the state before it.
+pub struct Keyframes {
+    keys: Vec<(u64, Value)>,
+}
+
+impl Keyframes {
+    /// Reads a JSON object mapping frame numbers to partial params, e.g.
+    /// `{"1": {"force": 0.5}, "240": {"force": 2.0, "color_mode": "Age"}}`.
+    /// Fields the first keyframe leaves out come from `base`.
+    pub fn load(path: &str, base: &Params) -> Result<Keyframes, SimError> {
+        let text = std::fs::read_to_string(path).map_err(SimError::io("reading", path))?;
+        Self::parse(&text, base).map_err(|e| e.in_file(path))
+    }
+
+    pub fn parse(text: &str, base: &Params) -> Result<Keyframes, SimError> {
+        let Value::Object(partials) = serde_json::from_str(text).map_err(SimError::decode("keyframes"))? else {
+            return Err(SimError::Invalid("expected an object of frame number to params".to_string()));
+        };
+        let mut partials: Vec<(u64, Map<String, Value>)> = partials
+            .into_iter()
+            .map(|(frame, params)| match (frame.parse(), params) {
+                (Ok(number), Value::Object(params)) => Ok((number, params)),
+                (Err(_), _) => Err(SimError::Invalid(format!("bad frame number {:?}", frame))),
+                (Ok(_), _) => Err(SimError::Invalid(format!("keyframe {} is not an object", frame))),
+            })
+            .collect::<Result<_, _>>()?;
+        if partials.is_empty() {
+            return Err(SimError::Invalid("no keyframes".to_string()));
+        }
+        partials.sort_by_key(|(frame, _)| *frame);
+
+        let Value::Object(mut state) = serde_json::to_value(base).map_err(SimError::encode("params"))? else {
+            unreachable!("params serialize to an object");
+        };
+        let mut keys = Vec::with_capacity(partials.len());
+        for (frame, partial) in partials {
+            for (key, value) in partial {
+                let Some(old) = state.get(&key) else {
+                    return Err(SimError::Invalid(format!("keyframe {}: unknown param {:?}", frame, key)));
+                };
+                let value = floats_like(value, old);
+                state.insert(key, value);
+            }
+            let full = Value::Object(state.clone());
+            // Catch bad values here rather than partway through the render
+            serde_json::from_value::<Params>(full.clone()).map_err(SimError::decode(format!("keyframe {}", frame)))?;
+            keys.push((frame, full));
+        }
+        Ok(Keyframes { keys })
+    }
+
+    /// Params at `frame`: numbers eased linearly between the keyframes
+    /// either side, everything else held from the earlier one until the
+    /// later one is reached. Before the first keyframe and after the last
+    /// they hold.
+    pub fn params_at(&self, frame: u64) -> Result<Params, SimError> {
+        let next = self.keys.partition_point(|(f, _)| *f <= frame);
+        let value = match (next.checked_sub(1).map(|i| &self.keys[i]), self.keys.get(next)) {
+            (Some((f0, a)), Some((f1, b))) => lerp_value(a, b, (frame - f0) as f64 / (f1 - f0) as f64),
+            (Some((_, a)), None) => a.clone(),
+            (None, Some((_, b))) => b.clone(),
+            (None, None) => unreachable!("parse rejects an empty list"),
+        };
+        serde_json::from_value(value).map_err(SimError::decode(format!("params at frame {}", frame)))
+    }
+}
+
+/// `value` with any integer that sits where `like` has a float turned into
+/// a float, so `"force": 2` eases smoothly instead of in whole steps.
+fn floats_like(value: Value, like: &Value) -> Value {
+    match (value, like) {
+        (Value::Number(n), Value::Number(l)) if l.is_f64() => n.as_f64().map_or(Value::Number(n), Value::from),
+        (Value::Array(xs), Value::Array(ls)) => Value::Array(
+            xs.into_iter()
+                .enumerate()
+                .map(|(i, x)| match ls.get(i).or(ls.first()) {
+                    Some(l) => floats_like(x, l),
+                    None => x,
+                })
+                .collect(),
+        ),
+        (Value::Object(xs), Value::Object(ls)) => Value::Object(
+            xs.into_iter()
+                .map(|(k, x)| {
+                    let x = match ls.get(&k) {
+                        Some(l) => floats_like(x, l),
+                        None => x,
+                    };
+                    (k, x)
+                })
+                .collect(),
+        ),
+        (value, _) => value,
+    }
+}
+
+/// `a` to `b` by `t`, through arrays and objects of matching shape.
+/// Integers stay integers; strings, bools and mismatched shapes are `a`.
+fn lerp_value(a: &Value, b: &Value, t: f64) -> Value {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => {
+            let (Some(x0), Some(y0)) = (x.as_f64(), y.as_f64()) else {
+                return a.clone();
+            };
+            let v = x0 + (y0 - x0) * t;
+            if x.is_u64() && y.is_u64() {
+                Value::from(v.round() as u64)
+            } else if x.is_i64() && y.is_i64() {
+                Value::from(v.round() as i64)
+            } else {
+                Value::from(v)
+            }
+        }
+        (Value::Array(xs), Value::Array(ys)) if xs.len() == ys.len() => {
+            Value::Array(xs.iter().zip(ys).map(|(x, y)| lerp_value(x, y, t)).collect())
+        }
+        (Value::Object(xs), Value::Object(ys)) => Value::Object(
+            xs.iter()
+                .map(|(k, x)| (k.clone(), ys.get(k).map_or_else(|| x.clone(), |y| lerp_value(x, y, t))))
+                .collect(),
+        ),
+        _ => a.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ColorMode;
+
+    fn keyframes(text: &str) -> Keyframes {
+        Keyframes::parse(text, &Params::new(100)).unwrap()
+    }
+
+    #[test]
+    fn integers_ease_as_floats_where_the_param_is_a_float() {
+        let keys = keyframes(r#"{"0": {"force": 0}, "10": {"force": 2}}"#);
+        assert_eq!(keys.params_at(5).unwrap().force, 1.0);
+        assert_eq!(keys.params_at(3).unwrap().force, 0.6);
+    }
+
+    #[test]
+    fn integer_params_stay_whole() {
+        let keys = keyframes(r#"{"0": {"steps_per_frame": 10}, "4": {"steps_per_frame": 13}}"#);
+        assert_eq!(keys.params_at(1).unwrap().steps_per_frame, 11);
+    }
+
+    #[test]
+    fn discrete_params_hold_until_the_later_keyframe() {
+        let keys = keyframes(r#"{"0": {"color_mode": "Age"}, "10": {"color_mode": "Speed"}}"#);
+        assert_eq!(keys.params_at(9).unwrap().color_mode, ColorMode::Age);
+        assert_eq!(keys.params_at(10).unwrap().color_mode, ColorMode::Speed);
+    }
+
+    #[test]
+    fn params_hold_outside_the_keyframes() {
+        let keys = keyframes(r#"{"5": {"force": 1.5}, "10": {"force": 3.0}}"#);
+        assert_eq!(keys.params_at(0).unwrap().force, 1.5);
+        assert_eq!(keys.params_at(50).unwrap().force, 3.0);
+    }
+
+    #[test]
+    fn unset_fields_come_from_the_base() {
+        let base = Params::new(100);
+        let keys = keyframes(r#"{"0": {"force": 1.5}}"#);
+        assert_eq!(keys.params_at(0).unwrap().friction, base.friction);
+    }
+
+    #[test]
+    fn bad_keyframes_are_rejected() {
+        let base = Params::new(100);
+        assert!(Keyframes::parse(r#"{"0": {"not_a_param": 1}}"#, &base).is_err());
+        assert!(Keyframes::parse(r#"{"soon": {"force": 1}}"#, &base).is_err());
+        assert!(Keyframes::parse(r#"{"0": 1}"#, &base).is_err());
+        assert!(Keyframes::parse(r#"{"0": {"color_mode": "Plaid"}}"#, &base).is_err());
+        assert!(Keyframes::parse("{}", &base).is_err());
+    }
+}
@@ -0,0 +1,428 @@
+//! Which key combination triggers which [`Action`]: the built-in bindings,
+//! with any rebound in `keymap.toml`.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::de::IntoDeserializer;
+use serde::Deserialize;
+use winit::event::{ModifiersState, VirtualKeyCode};
+
+pub const KEYMAP_PATH: &str = "keymap.toml";
+
+/// Something a key can do. Names in `keymap.toml` are these variant names,
+/// plus `ShadowSwatch1` to `ShadowSwatch9` and `HighlightSwatch1` to
+/// `HighlightSwatch9`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+pub enum Action {
+    TogglePause,
+    SavePng,
+    ToggleSvgRecording,
+    CopyToClipboard,
+    ReseedNoise,
+    Reset,
+    /// Reset and go back to the startup params.
+    ResetParams,
+    /// Reset with a new noise seed.
+    ResetReseed,
+    ResetParamsReseed,
+    ToggleGifRecording,
+    ToggleFreeze,
+    /// Clear the canvas and particles, spawn one `burst_count` burst and
+    /// stop spawning.
+    BurstShot,
+    SaveParams,
+    SaveState,
+    LoadState,
+    Undo,
+    ScaleDown,
+    ScaleUp,
+    ScaleXDown,
+    ScaleXUp,
+    ScaleYDown,
+    ScaleYUp,
+    ZStepDown,
+    ZStepUp,
+    GammaDown,
+    GammaUp,
+    ForceDown,
+    ForceUp,
+    FrictionDown,
+    FrictionUp,
+    FadeUp,
+    FadeDown,
+    CycleFadeMode,
+    CycleColorMode,
+    CycleAgeEasing,
+    CyclePalette,
+    CycleNoiseKind,
+    ToggleFieldMode,
+    ToggleIntegrator,
+    RotateFieldBack,
+    RotateFieldForward,
+    CycleDomainWarp,
+    AddNoiseLayer,
+    RemoveNoiseLayer,
+    CycleBoundaryMode,
+    CycleSpawnMode,
+    CycleSpawnBias,
+    ToggleAntialiasing,
+    ToggleSmoothTrails,
+    CycleThickness,
+    /// Lines, soft splats or both.
+    CycleSplatMode,
+    CycleBlendMode,
+    StrokeAlphaDown,
+    StrokeAlphaUp,
+    ToggleField,
+    ToggleDensity,
+    ToggleLfo,
+    ToggleHud,
+    OctavesDown,
+    OctavesUp,
+    ToggleRidged,
+    SaturationUp,
+    SaturationDown,
+    SymmetryUp,
+    SymmetryDown,
+    HueRateUp,
+    HueRateDown,
+    LifespanUp,
+    LifespanDown,
+    WindLeft,
+    WindRight,
+    WindUp,
+    WindDown,
+    /// Slide the view of the field a tenth of the canvas that way.
+    PanLeft,
+    PanRight,
+    PanUp,
+    PanDown,
+    ZoomIn,
+    ZoomOut,
+    /// Advance one frame while paused.
+    StepOnce,
+    SpawnMore,
+    SpawnFewer,
+    StepsUp,
+    StepsDown,
+    /// Set the duotone (or palette) shadow color to a HUD swatch, 0-based.
+    #[serde(skip)]
+    ShadowSwatch(usize),
+    #[serde(skip)]
+    HighlightSwatch(usize),
+}
+
+impl FromStr for Action {
+    type Err = serde::de::value::Error;
+
+    fn from_str(name: &str) -> Result<Action, Self::Err> {
+        let swatch = |prefix: &str| {
+            let digit: usize = name.strip_prefix(prefix)?.parse().ok()?;
+            (1..=9).contains(&digit).then(|| digit - 1)
+        };
+        if let Some(index) = swatch("ShadowSwatch") {
+            return Ok(Action::ShadowSwatch(index));
+        }
+        if let Some(index) = swatch("HighlightSwatch") {
+            return Ok(Action::HighlightSwatch(index));
+        }
+        Action::deserialize(name.into_deserializer())
+    }
+}
+
+/// A key plus the modifiers held with it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Chord {
+    pub key: VirtualKeyCode,
+    pub shift: bool,
+    pub ctrl: bool,
+}
+
+impl Chord {
+    const fn new(key: VirtualKeyCode) -> Chord {
+        Chord {
+            key,
+            shift: false,
+            ctrl: false,
+        }
+    }
+
+    const fn shift(key: VirtualKeyCode) -> Chord {
+        Chord {
+            shift: true,
+            ..Chord::new(key)
+        }
+    }
+
+    const fn ctrl(key: VirtualKeyCode) -> Chord {
+        Chord {
+            ctrl: true,
+            ..Chord::new(key)
+        }
+    }
+}
+
+/// `Shift+`/`Ctrl+` prefixes, in either order, then a `VirtualKeyCode`
+/// name, e.g. `Shift+LBracket`.
+impl FromStr for Chord {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Chord> {
+        let mut parts: Vec<&str> = text.split('+').map(str::trim).collect();
+        let key = parts.pop().unwrap_or_default();
+        let parsed: Result<VirtualKeyCode, serde::de::value::Error> =
+            VirtualKeyCode::deserialize(key.into_deserializer());
+        let key = parsed.map_err(|_| anyhow!("unknown key {:?}", key))?;
+        let mut chord = Chord::new(key);
+        for modifier in parts {
+            match modifier.to_ascii_lowercase().as_str() {
+                "shift" => chord.shift = true,
+                "ctrl" => chord.ctrl = true,
+                _ => bail!("unknown modifier {:?} in {:?}", modifier, text),
+            }
+        }
+        Ok(chord)
+    }
+}
+
+/// One or more chords for an action in `keymap.toml`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Chords {
+    One(String),
+    Many(Vec<String>),
+}
+
+pub struct Keymap {
+    bindings: HashMap<Chord, Action>,
+}
+
+impl Keymap {
+    /// The built-in bindings with `path`'s laid over them: an action the
+    /// file names keeps only the chords given there. A missing file leaves
+    /// the defaults.
+    pub fn load(path: &str) -> Result<Keymap> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Keymap::default()),
+            Err(e) => return Err(e).with_context(|| format!("reading {}", path)),
+        };
+        Keymap::parse(&text).with_context(|| format!("parsing {}", path))
+    }
+
+    fn parse(text: &str) -> Result<Keymap> {
+        let table: HashMap<String, Chords> = toml::from_str(text)?;
+        let mut rebound = Vec::new();
+        for (name, chords) in table {
+            let action: Action = name.parse().map_err(|_| anyhow!("unknown action {:?}", name))?;
+            let chords = match chords {
+                Chords::One(chord) => vec![chord],
+                Chords::Many(chords) => chords,
+            };
+            for chord in chords {
+                rebound.push((chord.parse::<Chord>()?, action));
+            }
+        }
+        let mut keymap = Keymap::default();
+        keymap.bindings.retain(|_, action| !rebound.iter().any(|(_, a)| a == action));
+        keymap.bindings.extend(rebound);
+        Ok(keymap)
+    }
+
+    /// The action for `key` with `modifiers` held. Without an exact match,
+    /// a held modifier the key has no binding for is ignored, Ctrl before
+    /// Shift, so e.g. Ctrl+Shift+LBracket falls back to Shift+LBracket.
+    pub fn action(&self, key: VirtualKeyCode, modifiers: ModifiersState) -> Option<Action> {
+        let chord = Chord {
+            key,
+            shift: modifiers.shift(),
+            ctrl: modifiers.ctrl(),
+        };
+        let fallbacks = [
+            chord,
+            Chord { ctrl: false, ..chord },
+            Chord { shift: false, ..chord },
+            Chord::new(key),
+        ];
+        fallbacks.iter().find_map(|c| self.bindings.get(c).copied())
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        use Action::*;
+        use VirtualKeyCode as K;
+        let mut bindings: HashMap<Chord, Action> = [
+            (Chord::new(K::Space), TogglePause),
+            (Chord::new(K::S), SavePng),
+            (Chord::shift(K::S), ToggleSvgRecording),
+            (Chord::ctrl(K::C), CopyToClipboard),
+            (Chord::new(K::R), ReseedNoise),
+            (Chord::new(K::Back), Reset),
+            (Chord::shift(K::Back), ResetParams),
+            (Chord::ctrl(K::Back), ResetReseed),
+            (
+                Chord {
+                    shift: true,
+                    ..Chord::ctrl(K::Back)
+                },
+                ResetParamsReseed,
+            ),
+            (Chord::new(K::V), ToggleGifRecording),
+            (Chord::new(K::Return), ToggleFreeze),
+            (Chord::new(K::Key1), BurstShot),
+            (Chord::new(K::W), SaveParams),
+            (Chord::new(K::F5), SaveState),
+            (Chord::new(K::F9), LoadState),
+            (Chord::new(K::U), Undo),
+            (Chord::new(K::LBracket), ScaleDown),
+            (Chord::new(K::RBracket), ScaleUp),
+            (Chord::shift(K::LBracket), ScaleXDown),
+            (Chord::shift(K::RBracket), ScaleXUp),
+            (Chord::ctrl(K::LBracket), ScaleYDown),
+            (Chord::ctrl(K::RBracket), ScaleYUp),
+            (Chord::new(K::Comma), ZStepDown),
+            (Chord::new(K::Period), ZStepUp),
+            (Chord::new(K::Semicolon), GammaDown),
+            (Chord::new(K::Apostrophe), GammaUp),
+            (Chord::new(K::Slash), ForceDown),
+            (Chord::new(K::Equals), ForceUp),
+            (Chord::new(K::Key9), FrictionDown),
+            (Chord::new(K::Key0), FrictionUp),
+            (Chord::new(K::F), FadeUp),
+            (Chord::new(K::G), FadeDown),
+            (Chord::shift(K::F), CycleFadeMode),
+            (Chord::new(K::C), CycleColorMode),
+            (Chord::shift(K::C), CycleAgeEasing),
+            (Chord::new(K::L), CyclePalette),
+            (Chord::new(K::N), CycleNoiseKind),
+            (Chord::new(K::E), ToggleFieldMode),
+            (Chord::new(K::Backslash), ToggleIntegrator),
+            (Chord::shift(K::E), RotateFieldBack),
+            (Chord::ctrl(K::E), RotateFieldForward),
+            (Chord::new(K::Y), CycleDomainWarp),
+            (Chord::new(K::Insert), AddNoiseLayer),
+            (Chord::new(K::Delete), RemoveNoiseLayer),
+            (Chord::new(K::B), CycleBoundaryMode),
+            (Chord::new(K::Tab), CycleSpawnMode),
+            (Chord::shift(K::Tab), CycleSpawnBias),
+            (Chord::new(K::A), ToggleAntialiasing),
+            (Chord::shift(K::A), ToggleSmoothTrails),
+            (Chord::new(K::T), CycleThickness),
+            (Chord::shift(K::T), CycleSplatMode),
+            (Chord::new(K::X), CycleBlendMode),
+            (Chord::shift(K::X), StrokeAlphaDown),
+            (Chord::ctrl(K::X), StrokeAlphaUp),
+            (Chord::new(K::Q), ToggleField),
+            (Chord::shift(K::Q), ToggleDensity),
+            (Chord::new(K::Z), ToggleLfo),
+            (Chord::new(K::H), ToggleHud),
+            (Chord::new(K::O), OctavesDown),
+            (Chord::new(K::P), OctavesUp),
+            (Chord::new(K::I), ToggleRidged),
+            (Chord::new(K::D), SaturationUp),
+            (Chord::shift(K::D), SaturationDown),
+            (Chord::new(K::K), SymmetryUp),
+            (Chord::shift(K::K), SymmetryDown),
+            (Chord::new(K::J), HueRateUp),
+            (Chord::shift(K::J), HueRateDown),
+            (Chord::new(K::M), LifespanUp),
+            (Chord::shift(K::M), LifespanDown),
+            (Chord::ctrl(K::Left), WindLeft),
+            (Chord::ctrl(K::Right), WindRight),
+            (Chord::ctrl(K::Up), WindUp),
+            (Chord::ctrl(K::Down), WindDown),
+            (Chord::shift(K::Left), PanLeft),
+            (Chord::shift(K::Right), PanRight),
+            (Chord::shift(K::Up), PanUp),
+            (Chord::shift(K::Down), PanDown),
+            (Chord::shift(K::Equals), ZoomIn),
+            (Chord::new(K::NumpadAdd), ZoomIn),
+            (Chord::new(K::Minus), ZoomOut),
+            (Chord::new(K::NumpadSubtract), ZoomOut),
+            (Chord::new(K::Right), StepOnce),
+            (Chord::new(K::Up), SpawnMore),
+            (Chord::new(K::Down), SpawnFewer),
+            (Chord::new(K::PageUp), StepsUp),
+            (Chord::new(K::PageDown), StepsDown),
+        ]
+        .into_iter()
+        .collect();
+        const DIGITS: [VirtualKeyCode; 9] =
+            [K::Key1, K::Key2, K::Key3, K::Key4, K::Key5, K::Key6, K::Key7, K::Key8, K::Key9];
+        for (i, &key) in DIGITS.iter().enumerate() {
+            bindings.insert(Chord::shift(key), ShadowSwatch(i));
+            bindings.insert(Chord::ctrl(key), HighlightSwatch(i));
+        }
+        Keymap { bindings }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use VirtualKeyCode as K;
+
+    fn held(shift: bool, ctrl: bool) -> ModifiersState {
+        let mut modifiers = ModifiersState::empty();
+        modifiers.set(ModifiersState::SHIFT, shift);
+        modifiers.set(ModifiersState::CTRL, ctrl);
+        modifiers
+    }
+
+    #[test]
+    fn chord_prefixes_parse_in_either_order() {
+        let both = Chord {
+            key: K::LBracket,
+            shift: true,
+            ctrl: true,
+        };
+        assert_eq!("Shift+Ctrl+LBracket".parse::<Chord>().unwrap(), both);
+        assert_eq!("ctrl + shift + LBracket".parse::<Chord>().unwrap(), both);
+        assert_eq!("F1".parse::<Chord>().unwrap(), Chord::new(K::F1));
+    }
+
+    #[test]
+    fn unknown_keys_and_modifiers_are_errors() {
+        assert!("Shift+NotAKey".parse::<Chord>().is_err());
+        assert!("Alt+S".parse::<Chord>().is_err());
+    }
+
+    #[test]
+    fn swatch_names_parse_one_based() {
+        assert_eq!("ShadowSwatch1".parse::<Action>().unwrap(), Action::ShadowSwatch(0));
+        assert_eq!("HighlightSwatch9".parse::<Action>().unwrap(), Action::HighlightSwatch(8));
+        assert!("ShadowSwatch0".parse::<Action>().is_err());
+        assert!("ShadowSwatch10".parse::<Action>().is_err());
+        assert_eq!("ToggleHud".parse::<Action>().unwrap(), Action::ToggleHud);
+    }
+
+    #[test]
+    fn rebinding_an_action_drops_its_default_chords() {
+        let keymap = Keymap::parse("ToggleHud = \"F1\"\nSavePng = [\"F2\", \"Ctrl+F2\"]").unwrap();
+        assert_eq!(keymap.action(K::F1, held(false, false)), Some(Action::ToggleHud));
+        assert_eq!(keymap.action(K::H, held(false, false)), None);
+        assert_eq!(keymap.action(K::F2, held(false, true)), Some(Action::SavePng));
+        assert_eq!(keymap.action(K::S, held(false, false)), None);
+        // Other actions keep their defaults
+        assert_eq!(keymap.action(K::Space, held(false, false)), Some(Action::TogglePause));
+    }
+
+    #[test]
+    fn unknown_actions_and_keys_in_the_file_are_errors() {
+        assert!(Keymap::parse("Explode = \"F1\"").is_err());
+        assert!(Keymap::parse("ToggleHud = \"NotAKey\"").is_err());
+    }
+
+    #[test]
+    fn unbound_modifiers_fall_back_ctrl_first() {
+        let keymap = Keymap::default();
+        // Shift+LBracket is bound and Ctrl+Shift+LBracket isn't, so Ctrl goes first
+        assert_eq!(keymap.action(K::LBracket, held(true, true)), Some(Action::ScaleXDown));
+        assert_eq!(keymap.action(K::LBracket, held(false, true)), Some(Action::ScaleYDown));
+        // Nothing on Shift+R, so the plain key
+        assert_eq!(keymap.action(K::R, held(true, false)), Some(Action::ReseedNoise));
+    }
+}
@@ -0,0 +1,1748 @@
+//! Flow-field particle simulation, independent of any window.
+//!
+//! A [`Simulator`] advances particles through a noise-driven vector field and
+//! rasterizes the segments they trace into a caller-owned RGBA8 frame. The
+//! stepping runs on the CPU unless [`Simulator::enable_gpu`] succeeds.
+//! Everything fallible returns a [`SimError`].
+
+use std::path::Path;
+use std::sync::Mutex;
+
+use glam::{Vec2, Vec3};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+// Build-time configuration values generated by build.rs (written into src/)
+pub mod build_config;
+pub mod color;
+pub mod easing;
+pub mod error;
+pub mod field;
+pub mod font;
+pub mod gpu;
+pub mod keyframes;
+pub mod palette;
+pub mod params;
+pub mod raster;
+pub mod svg;
+pub mod trace;
+
+pub use color::hsv_to_rgb;
+pub use error::SimError;
+pub use field::{curl_dir, noise_angle, noise_dir, noise_value, warp_domain, FlowNoise, ImageField};
+pub use params::{
+    AgeEasing, Attractor, BlendMode, BoundaryMode, ColorMode, DomainWarp, EmitterMode, FadeMode, FieldMode, Integrator, NoiseKind, NoiseLayer, Params, ScalarSource, SpawnBias, SpawnMode,
+    SplatMode,
+};
+pub use trace::{TracePoint, Tracer};
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Particle {
+    pub pos: Vec2,
+    pub vel: Vec2,
+    pub age: u32,
+    pub alive: bool,
+    /// Added to the hue (or palette position) of every segment this particle
+    /// draws; drawn once at spawn from `Params::hue_jitter`.
+    pub hue_offset: f32,
+    /// Divides the field force on the particle, so heavy ones drift where
+    /// light ones dart; drawn at spawn from `Params::mass_range`.
+    pub mass: f32,
+    /// Its latest positions, which smooth trails draw their curve through.
+    pub trail: Trail,
+    /// Color of the last segment drawn, in 0..255 per channel, which
+    /// `Params::color_smoothing` eases the next one from; None until the
+    /// first.
+    pub color: Option<Vec3>,
+}
+
+impl Particle {
+    pub fn new(pos: Vec2) -> Self {
+        Self {
+            pos,
+            vel: Vec2::ZERO,
+            age: 0,
+            alive: true,
+            hue_offset: 0.0,
+            mass: 1.0,
+            trail: Trail::new(pos),
+            color: None,
+        }
+    }
+}
+
+/// Most positions a `Trail` holds, whatever `Params::trail_len` asks for.
+pub const MAX_TRAIL_LEN: usize = 8;
+
+/// Ring buffer of a particle's latest positions, newest last.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Trail {
+    points: [Vec2; MAX_TRAIL_LEN],
+    /// Index of the newest point.
+    head: usize,
+    len: usize,
+}
+
+impl Trail {
+    /// A trail holding just `pos`.
+    pub fn new(pos: Vec2) -> Self {
+        let mut points = [Vec2::ZERO; MAX_TRAIL_LEN];
+        points[0] = pos;
+        Self { points, head: 0, len: 1 }
+    }
+
+    /// Adds `pos` as the newest point, dropping the oldest ones past `cap`
+    /// (held to `3..=MAX_TRAIL_LEN`, the fewest a curve is drawn through).
+    pub fn push(&mut self, pos: Vec2, cap: usize) {
+        self.head = (self.head + 1) % MAX_TRAIL_LEN;
+        self.points[self.head] = pos;
+        self.len = (self.len + 1).min(cap.clamp(3, MAX_TRAIL_LEN));
+    }
+
+    /// The point `back` pushes before the newest, or the oldest held if
+    /// there aren't that many.
+    pub fn back(&self, back: usize) -> Vec2 {
+        let back = back.min(self.len - 1);
+        self.points[(self.head + MAX_TRAIL_LEN - back) % MAX_TRAIL_LEN]
+    }
+}
+
+/// Seeds for the noise field and the particle RNG.
+#[derive(Clone, Copy, Debug)]
+pub struct Seeds {
+    pub noise: u32,
+    pub rng: u64,
+}
+
+impl Seeds {
+    /// Derives both seeds from a single user-supplied value.
+    pub fn from_u64(seed: u64) -> Self {
+        Self {
+            noise: (seed ^ (seed >> 32)) as u32,
+            rng: seed,
+        }
+    }
+}
+
+impl Default for Seeds {
+    fn default() -> Self {
+        Self {
+            noise: build_config::NOISE_SEED,
+            rng: build_config::RNG_SEED,
+        }
+    }
+}
+
+/// Everything needed to resume a simulation exactly where it left off,
+/// including the RNG's position in its stream.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SimState {
+    pub width: u32,
+    pub height: u32,
+    /// Includes the running z positions of the base field and layers.
+    pub params: Params,
+    pub particles: Vec<Particle>,
+    pub noise_seed: u32,
+    pub rng: ChaCha12Rng,
+    pub frames: u64,
+}
+
+/// On-disk checkpoint: the simulation plus the frame it has drawn so far, so
+/// trails resume intact.
+#[derive(Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub sim: SimState,
+    pub frame_index: u64,
+    /// RGBA8, `sim.width * sim.height * 4` bytes.
+    pub frame: Vec<u8>,
+}
+
+impl Checkpoint {
+    pub fn save(&self, path: &str) -> Result<(), SimError> {
+        let file = std::fs::File::create(path).map_err(SimError::io("creating", path))?;
+        bincode::serialize_into(std::io::BufWriter::new(file), self).map_err(SimError::encode("checkpoint"))
+    }
+
+    /// Reads a checkpoint written by `save`, checking its frame matches the
+    /// size it was saved at.
+    pub fn load(path: &str) -> Result<Checkpoint, SimError> {
+        let file = std::fs::File::open(path).map_err(SimError::io("opening", path))?;
+        let checkpoint: Checkpoint =
+            bincode::deserialize_from(std::io::BufReader::new(file)).map_err(SimError::decode(path))?;
+        let (width, height) = (checkpoint.sim.width, checkpoint.sim.height);
+        let expected = width as usize * height as usize * 4;
+        if checkpoint.frame.len() != expected {
+            return Err(SimError::BufferDims {
+                width,
+                height,
+                expected,
+                actual: checkpoint.frame.len(),
+            });
+        }
+        Ok(checkpoint)
+    }
+
+    /// Errs unless the checkpoint was saved on a canvas of this size.
+    pub fn check_size(&self, width: u32, height: u32) -> Result<(), SimError> {
+        let saved = (self.sim.width, self.sim.height);
+        if saved != (width, height) {
+            return Err(SimError::CanvasSize {
+                saved,
+                canvas: (width, height),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Writes an RGBA8 frame of `width * height * 4` bytes as a PNG.
+pub fn save_png(path: &Path, width: u32, height: u32, rgba: &[u8]) -> Result<(), SimError> {
+    let expected = width as usize * height as usize * 4;
+    if rgba.len() != expected {
+        return Err(SimError::BufferDims {
+            width,
+            height,
+            expected,
+            actual: rgba.len(),
+        });
+    }
+    image::save_buffer(path, rgba, width, height, image::ColorType::Rgba8).map_err(SimError::image("writing", path))
+}
+
+/// One straight piece of a particle trail, waiting to be rasterized.
+#[derive(Clone, Copy)]
+pub struct Segment {
+    pub p0: Vec2,
+    pub p1: Vec2,
+    pub color: (u8, u8, u8),
+    /// Line width in pixels; 1 is a plain single-pixel line.
+    pub width: f32,
+}
+
+/// Side in pixels of the squares particles are counted in for the density
+/// overlay.
+const DENSITY_CELL: u32 = 16;
+
+pub struct Simulator {
+    width: u32,
+    height: u32,
+    pub params: Params,
+    noise: FlowNoise,
+    noise_seed: u32,
+    /// The same generator `StdRng` wraps, used directly so it can be saved.
+    rng: ChaCha12Rng,
+    pub particles: Vec<Particle>,
+    /// Gradient used by `ColorMode::Palette`.
+    pub palette: Vec<palette::Stop>,
+    /// When set, particles are pulled toward this point each substep.
+    pub attractor: Option<Vec2>,
+    /// Replaces the noise field as the source of flow directions when set.
+    pub image_field: Option<ImageField>,
+    /// Picture `clear` paints instead of the background color, stretched to
+    /// the frame. Fading is skipped while it is set so it isn't worn away.
+    pub background: Option<image::RgbImage>,
+    /// Frame rate the simulated time per frame is taken from; see
+    /// `substep_dt`.
+    pub target_fps: f32,
+    /// Segments traced by `step` since the last `render_into`.
+    segments: Vec<Segment>,
+    segments_drawn: usize,
+    /// Steps taken so far; the clock for the params' LFOs.
+    frames: u64,
+    /// Most particle slots, alive or dead, held at once since the last reset.
+    particle_high_water: usize,
+    /// `loop_frames`, the rates z, each layer's z and the hue shift move
+    /// at, and where each of them was when the current loop began.
+    loop_start: Option<(u64, Vec<f32>, Vec<f32>)>,
+    /// Emitter `EmitterMode::Cycle` respawns the next particle at.
+    next_emitter: usize,
+    /// Layout slot `spawn_trickle` places its next particle in.
+    next_spawn_slot: usize,
+    /// Live particles per `DENSITY_CELL` square after the last step, row
+    /// by row.
+    density: Vec<u32>,
+    /// Copies of rendered segments kept for vector export, and their cap.
+    recording: Option<(Vec<Segment>, usize)>,
+    /// Factor and RGBA8 buffer of a hi-res copy of the frame, drawn alongside
+    /// it by `render_into` for supersampled exports.
+    supersample: Option<(u32, Vec<u8>)>,
+    /// Compute-shader stepper, used on frames whose params it supports.
+    gpu: Option<gpu::GpuStepper>,
+    /// Unclipped RGB float copy of the frame, 1 being a full 8-bit channel,
+    /// that `render_into` accumulates the same segments into for HDR export.
+    hdr: Option<Vec<f32>>,
+    /// Paths of the particles followed since `start_tracing`.
+    trace: Option<Tracer>,
+}
+
+impl Simulator {
+    pub fn new(width: u32, height: u32, params: Params, seeds: Seeds) -> Self {
+        let noise_seed = seeds.noise;
+        let noise = FlowNoise::new(params.noise_kind, noise_seed);
+        let rng = ChaCha12Rng::seed_from_u64(seeds.rng);
+
+        Self {
+            width,
+            height,
+            params,
+            noise,
+            noise_seed,
+            rng,
+            particles: Vec::with_capacity((width * height / 4) as usize),
+            palette: palette::BUILTIN[0].1.to_vec(),
+            attractor: None,
+            image_field: None,
+            background: None,
+            target_fps: 60.0,
+            segments: Vec::new(),
+            segments_drawn: 0,
+            frames: 0,
+            particle_high_water: 0,
+            loop_start: None,
+            next_emitter: 0,
+            next_spawn_slot: 0,
+            density: Vec::new(),
+            recording: None,
+            supersample: None,
+            hdr: None,
+            gpu: None,
+            trace: None,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn noise_seed(&self) -> u32 {
+        self.noise_seed
+    }
+
+    /// Number of segments rasterized by the last `render_into`.
+    pub fn segments_drawn(&self) -> usize {
+        self.segments_drawn
+    }
+
+    /// Moves stepping onto the GPU. On error the simulator stays on the CPU.
+    pub fn enable_gpu(&mut self) -> Result<(), SimError> {
+        self.gpu = Some(gpu::GpuStepper::new(self.noise_seed)?);
+        Ok(())
+    }
+
+    /// Whether the next `step` will run on the GPU. Recording and
+    /// supersampling need the individual segments, so they keep stepping on
+    /// the CPU.
+    pub fn steps_on_gpu(&self) -> bool {
+        self.gpu.is_some()
+            && self.image_field.is_none()
+            && self.recording.is_none()
+            && self.supersample.is_none()
+            && self.hdr.is_none()
+            && self.trace.is_none()
+            && gpu::GpuStepper::supports(&self.params)
+    }
+
+    /// Starts keeping every segment `render_into` draws, up to `cap`.
+    pub fn start_recording(&mut self, cap: usize) {
+        self.recording = Some((Vec::new(), cap));
+    }
+
+    /// Stops recording and returns what was kept.
+    pub fn take_recording(&mut self) -> Option<Vec<Segment>> {
+        self.recording.take().map(|(segments, _)| segments)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Whether the recording has reached its cap.
+    pub fn recording_full(&self) -> bool {
+        self.recording.as_ref().is_some_and(|(segments, cap)| segments.len() >= *cap)
+    }
+
+    /// Starts following the next `count` particles to come alive, recording
+    /// their position every substep. Stepping stays on the CPU meanwhile.
+    pub fn start_tracing(&mut self, count: usize) {
+        self.trace = Some(Tracer::new(count));
+    }
+
+    /// Stops tracing and returns the paths followed.
+    pub fn take_trace(&mut self) -> Option<Tracer> {
+        self.trace.take()
+    }
+
+    pub fn live_particles(&self) -> usize {
+        self.particles.iter().filter(|p| p.alive).count()
+    }
+
+    /// Most particle slots held at once since the last reset.
+    pub fn particle_high_water(&self) -> usize {
+        self.particle_high_water
+    }
+
+    /// Drops dead particles, keeping the live ones in order, and gives back
+    /// memory once the list is well under its capacity. Spawning then pushes
+    /// new particles rather than refilling slots.
+    pub fn compact_particles(&mut self) {
+        if let Some(trace) = &mut self.trace {
+            trace.compact(&self.particles);
+        }
+        self.particles.retain(|p| p.alive);
+        if self.particles.capacity() > 2 * self.particles.len() {
+            self.particles.shrink_to(2 * self.particles.len());
+        }
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        self.width = width;
+        self.height = height;
+        if let Some((factor, _)) = self.supersample {
+            self.enable_supersampling(factor);
+        }
+        if self.hdr.is_some() {
+            self.enable_hdr();
+        }
+    }
+
+    /// Starts keeping a `factor`x resolution copy of the frame, cleared to
+    /// the background, that `render_into` draws the same segments into.
+    pub fn enable_supersampling(&mut self, factor: u32) {
+        let len = (self.width * factor) as usize * (self.height * factor) as usize * 4;
+        let mut buffer = vec![0u8; len];
+        self.clear_scaled(&mut buffer, factor);
+        self.supersample = Some((factor, buffer));
+    }
+
+    /// Starts keeping an HDR copy of the frame, cleared to the background.
+    /// Stepping stays on the CPU while it is on, since the GPU composites
+    /// straight into the 8-bit frame.
+    pub fn enable_hdr(&mut self) {
+        let mut frame = vec![0u8; (self.width * self.height * 4) as usize];
+        self.clear(&mut frame);
+        self.hdr = Some(hdr_from(&frame));
+    }
+
+    pub fn is_hdr(&self) -> bool {
+        self.hdr.is_some()
+    }
+
+    /// The HDR copy tone-mapped into a 16-bit image, with `params.hdr_white`
+    /// as the white point so highlights grade off instead of clipping;
+    /// `None` unless `enable_hdr` was called.
+    pub fn hdr_image(&self) -> Option<image::ImageBuffer<image::Rgb<u16>, Vec<u16>>> {
+        let hdr = self.hdr.as_ref()?;
+        let white = self.params.hdr_white;
+        let data = hdr
+            .iter()
+            .map(|&v| (color::tone_map(v, white) * 65535.0).round() as u16)
+            .collect();
+        image::ImageBuffer::from_raw(self.width, self.height, data)
+    }
+
+    /// Writes `hdr_image` to `path` as a 16-bit PNG.
+    pub fn save_hdr(&self, path: &Path) -> Result<(), SimError> {
+        let Some(img) = self.hdr_image() else {
+            return Err(SimError::Invalid("HDR accumulation is off".to_string()));
+        };
+        img.save(path).map_err(SimError::image("writing", path))
+    }
+
+    /// With HDR on, replaces the colors of the canvas-sized `frame` with the
+    /// HDR copy tone-mapped like `hdr_image`, so the 8-bit view shows the
+    /// highlights the accumulating frame clips. Like the post effects, it is
+    /// for the shown and exported frame only.
+    pub fn apply_hdr_tone_map(&self, frame: &mut [u8]) {
+        let Some(hdr) = &self.hdr else {
+            return;
+        };
+        let white = self.params.hdr_white;
+        for (px, v) in frame.chunks_exact_mut(4).zip(hdr.chunks_exact(3)) {
+            for (c, &v) in px.iter_mut().zip(v) {
+                *c = (color::tone_map(v, white) * 255.0).round() as u8;
+            }
+        }
+    }
+
+    /// Fills an RGBA8 `frame` with the opaque background color, or the
+    /// background image if one is set.
+    pub fn clear(&self, frame: &mut [u8]) {
+        self.clear_scaled(frame, 1);
+    }
+
+    /// `clear` for a frame `factor` times the canvas size.
+    fn clear_scaled(&self, frame: &mut [u8], factor: u32) {
+        if let Some(image) = &self.background {
+            let (width, height) = (self.width * factor, self.height * factor);
+            let scaled = image::imageops::resize(image, width, height, image::imageops::FilterType::Triangle);
+            for (px, rgb) in frame.chunks_exact_mut(4).zip(scaled.pixels()) {
+                px.copy_from_slice(&[rgb[0], rgb[1], rgb[2], 255]);
+            }
+            return;
+        }
+        let (r, g, b) = self.params.bg;
+        for px in frame.chunks_exact_mut(4) {
+            px.copy_from_slice(&[r, g, b, 255]);
+        }
+    }
+
+    /// The supersampling factor and hi-res frame, if enabled.
+    pub fn supersampled(&self) -> Option<(u32, &[u8])> {
+        self.supersample.as_ref().map(|(factor, buffer)| (*factor, buffer.as_slice()))
+    }
+
+    /// Replaces the params wholesale, keeping the running z positions of the
+    /// base field and of any layers that still exist, and the hue offset.
+    pub fn set_params(&mut self, mut params: Params) {
+        params.z = self.params.z;
+        params.hue_shift = self.params.hue_shift;
+        for (new, old) in params.layers.iter_mut().zip(&self.params.layers) {
+            new.z = old.z;
+        }
+        let rebuild = params.noise_kind != self.params.noise_kind;
+        self.params = params;
+        if rebuild {
+            self.set_noise_kind(self.params.noise_kind);
+        }
+    }
+
+    /// Rebuilds the noise field as `kind`, keeping the current seed.
+    pub fn set_noise_kind(&mut self, kind: NoiseKind) {
+        self.params.noise_kind = kind;
+        self.noise = FlowNoise::new(kind, self.noise_seed);
+    }
+
+    /// Rebuilds the active noise field with a fresh seed drawn from the RNG
+    /// and returns it.
+    pub fn reseed_noise(&mut self) -> u32 {
+        let seed: u32 = self.rng.gen();
+        self.noise_seed = seed;
+        self.noise = FlowNoise::new(self.params.noise_kind, seed);
+        if let Some(gpu) = &mut self.gpu {
+            gpu.set_seed(seed);
+        }
+        seed
+    }
+
+    /// Snapshot of the simulation for `restore`.
+    pub fn state(&self) -> SimState {
+        SimState {
+            width: self.width,
+            height: self.height,
+            params: self.params.clone(),
+            particles: self.particles.clone(),
+            noise_seed: self.noise_seed,
+            rng: self.rng.clone(),
+            frames: self.frames,
+        }
+    }
+
+    /// Resumes from a snapshot taken by `state`. Segments traced but not yet
+    /// rendered are dropped. `frame` is the canvas saved with it, which an
+    /// HDR copy starts over from.
+    pub fn restore(&mut self, state: SimState, frame: &[u8]) {
+        self.width = state.width;
+        self.height = state.height;
+        self.params = state.params;
+        self.particles = state.particles;
+        self.noise_seed = state.noise_seed;
+        self.noise = FlowNoise::new(self.params.noise_kind, self.noise_seed);
+        if let Some(gpu) = &mut self.gpu {
+            gpu.set_seed(self.noise_seed);
+        }
+        self.rng = state.rng;
+        self.frames = state.frames;
+        self.loop_start = None;
+        self.segments.clear();
+        if let Some(trace) = &mut self.trace {
+            trace.close_all();
+        }
+        // The hi-res copy can't be recovered from the snapshot, so restart it
+        if let Some((factor, _)) = self.supersample {
+            self.enable_supersampling(factor);
+        }
+        if self.hdr.is_some() {
+            self.hdr = Some(hdr_from(frame));
+        }
+    }
+
+    /// Drops every particle and pending segment and rewinds z, the layers'
+    /// z, the hue offset and the LFO clock to zero. Params are otherwise
+    /// kept.
+    pub fn reset(&mut self) {
+        self.clear_particles();
+        self.params.z = 0.0;
+        for layer in &mut self.params.layers {
+            layer.z = 0.0;
+        }
+        self.params.hue_shift = 0.0;
+        self.frames = 0;
+        self.loop_start = None;
+        self.particle_high_water = 0;
+        self.next_emitter = 0;
+        self.next_spawn_slot = 0;
+        if let Some((factor, _)) = self.supersample {
+            self.enable_supersampling(factor);
+        }
+        if self.hdr.is_some() {
+            self.enable_hdr();
+        }
+    }
+
+    /// Drops every particle and pending segment, leaving z and the rest of
+    /// the params where they are.
+    pub fn clear_particles(&mut self) {
+        self.particles.clear();
+        self.segments.clear();
+        if let Some(trace) = &mut self.trace {
+            trace.close_all();
+        }
+    }
+
+    /// Field direction at `p` for the current params and z slice.
+    pub fn field_dir(&self, p: Vec2) -> Vec2 {
+        self.field_dir_with(&self.params.modulated(self.frames), p)
+    }
+
+    /// `field_dir` with this frame's modulated `params` worked out already,
+    /// for callers looking up many points at once.
+    fn field_dir_with(&self, params: &Params, p: Vec2) -> Vec2 {
+        match &self.image_field {
+            Some(field) => Vec2::from_angle(params.field_rotation).rotate(field.dir(p, self.width, self.height)),
+            None => {
+                let size = Vec2::new(self.width as f32, self.height as f32);
+                noise_dir(&self.noise, params, warp_domain(params.domain_warp, p, size), 0.0, size)
+            }
+        }
+    }
+
+    /// Spawns `spawn_count` particles laid out per `spawn_mode`, reusing
+    /// dead slots first.
+    pub fn spawn(&mut self) {
+        self.spawn_n(self.params.spawn_count);
+    }
+
+    /// `spawn` with an explicit count, which also sizes the layout.
+    pub fn spawn_n(&mut self, count: usize) {
+        self.spawn_slots(count, count, 0);
+    }
+
+    /// Spawns `count` particles in the slots after the last call's, of a
+    /// layout sized to `spawn_count`. For `spawn_rate`, whose few per frame
+    /// would otherwise land on the same few spots every time.
+    pub fn spawn_trickle(&mut self, count: usize) {
+        let layout = self.params.spawn_count.max(count).max(1);
+        let first = self.next_spawn_slot % layout;
+        self.spawn_slots(count, layout, first);
+        self.next_spawn_slot = (first + count) % layout;
+    }
+
+    /// Spawns `count` particles in slots `first` onward of a `layout`-slot
+    /// layout, wrapping round, reusing dead particles first.
+    fn spawn_slots(&mut self, count: usize, layout: usize, first: usize) {
+        let size = Vec2::new(self.width as f32, self.height as f32);
+        let mode = self.params.spawn_mode;
+        if count == 0 {
+            return;
+        }
+        let mut spawned = 0usize;
+        let mut i = 0usize;
+        while spawned < count && i < self.particles.len() {
+            if !self.particles[i].alive {
+                let pos = self.spawn_position(mode, (first + spawned) % layout, layout, size);
+                self.particles[i] = self.new_particle(pos);
+                spawned += 1;
+            }
+            i += 1;
+        }
+        while spawned < count {
+            let pos = self.spawn_position(mode, (first + spawned) % layout, layout, size);
+            let particle = self.new_particle(pos);
+            self.particles.push(particle);
+            spawned += 1;
+        }
+    }
+
+    /// `spawn_position`, with uniform positions rejection-sampled per
+    /// `params.spawn_bias`. After a few rejections the last candidate is
+    /// taken anyway, so a flat field can't stall spawning.
+    fn spawn_position(&mut self, mode: SpawnMode, i: usize, count: usize, size: Vec2) -> Vec2 {
+        const TRIES: usize = 16;
+        let bias = self.params.spawn_bias;
+        let mut pos = spawn_position(mode, i, count, size, &mut self.rng);
+        if mode != SpawnMode::Uniform || bias == SpawnBias::None {
+            return pos;
+        }
+        for _ in 1..TRIES {
+            let lookup = warp_domain(self.params.domain_warp, pos, size);
+            let magnitude = noise_value(&self.noise, &self.params, lookup, 0.0, size).abs().min(1.0);
+            let keep = match bias {
+                SpawnBias::Low => 1.0 - magnitude,
+                _ => magnitude,
+            };
+            if self.rng.gen::<f32>() < keep {
+                break;
+            }
+            pos = spawn_position(mode, i, count, size, &mut self.rng);
+        }
+        pos
+    }
+
+    /// A fresh particle at `pos` with its hue offset drawn. Nothing is drawn
+    /// from the RNG while `hue_jitter` is 0, so seeded runs are unchanged.
+    fn new_particle(&mut self, pos: Vec2) -> Particle {
+        let mut particle = Particle::new(pos);
+        if self.params.hue_jitter > 0.0 {
+            particle.hue_offset = self.rng.gen_range(-0.5f32..0.5f32) * self.params.hue_jitter;
+        }
+        let (lo, hi) = self.params.mass_range;
+        let mass = if hi > lo { self.rng.gen_range(lo..hi) } else { lo };
+        // Massless particles would take an infinite kick
+        particle.mass = mass.max(0.01);
+        particle
+    }
+
+    /// Spawns `count` particles in a small cluster around `center`, reusing
+    /// dead slots first.
+    pub fn spawn_burst(&mut self, center: Vec2, count: usize) {
+        let mut i = 0usize;
+        for _ in 0..count {
+            let jitter = Vec2::new(
+                self.rng.gen_range(-4.0f32..4.0f32),
+                self.rng.gen_range(-4.0f32..4.0f32),
+            );
+            let particle = self.new_particle(center + jitter);
+            while i < self.particles.len() && self.particles[i].alive {
+                i += 1;
+            }
+            if i < self.particles.len() {
+                self.particles[i] = particle;
+                i += 1;
+            } else {
+                self.particles.push(particle);
+            }
+        }
+    }
+
+    fn density_dims(&self) -> (usize, usize) {
+        (
+            self.width.div_ceil(DENSITY_CELL) as usize,
+            self.height.div_ceil(DENSITY_CELL) as usize,
+        )
+    }
+
+    /// Recounts the live particles into `density`, sized to the canvas.
+    fn count_density(&mut self) {
+        let (cols, rows) = self.density_dims();
+        self.density.clear();
+        self.density.resize(cols * rows, 0);
+        for p in self.particles.iter().filter(|p| p.alive) {
+            if p.pos.x < 0.0 || p.pos.y < 0.0 {
+                continue;
+            }
+            let (col, row) = (p.pos.x as usize / DENSITY_CELL as usize, p.pos.y as usize / DENSITY_CELL as usize);
+            if col < cols && row < rows {
+                self.density[row * cols + col] += 1;
+            }
+        }
+    }
+
+    /// Most live particles in any one density cell after the last step.
+    pub fn peak_density(&self) -> u32 {
+        self.density.iter().copied().max().unwrap_or(0)
+    }
+
+    /// Tints each occupied density cell of `frame` half way toward blue
+    /// (one particle) through to red (the busiest cell).
+    pub fn draw_density(&self, frame: &mut [u8], width: u32, height: u32) {
+        let (cols, rows) = self.density_dims();
+        let peak = self.peak_density();
+        if peak == 0 || self.density.len() != cols * rows {
+            return;
+        }
+        for (i, &count) in self.density.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            let t = if peak > 1 { (count - 1) as f32 / (peak - 1) as f32 } else { 1.0 };
+            let (r, g, b) = hsv_to_rgb(0.66 * (1.0 - t), 1.0, 1.0);
+            let (x0, y0) = ((i % cols) as u32 * DENSITY_CELL, (i / cols) as u32 * DENSITY_CELL);
+            for y in y0..(y0 + DENSITY_CELL).min(height) {
+                for x in x0..(x0 + DENSITY_CELL).min(width) {
+                    let px = &mut frame[((y * width + x) * 4) as usize..][..3];
+                    for (c, tint) in px.iter_mut().zip([r, g, b]) {
+                        *c = ((*c as u16 + tint as u16) / 2) as u8;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Brings every dead particle back at one of `params.emitters`, chosen
+    /// per `params.emitter_mode`, scattered within `emitter_spread`.
+    fn respawn_at_emitters(&mut self) {
+        let count = self.params.emitters.len();
+        if count == 0 || self.params.emitter_mode == EmitterMode::None {
+            return;
+        }
+        let spread = self.params.emitter_spread.max(0.0);
+        for i in 0..self.particles.len() {
+            if self.particles[i].alive {
+                continue;
+            }
+            let index = match self.params.emitter_mode {
+                EmitterMode::Random => self.rng.gen_range(0..count),
+                _ => {
+                    // Emitters can be removed between frames, so wrap before reading
+                    let index = self.next_emitter % count;
+                    self.next_emitter = (index + 1) % count;
+                    index
+                }
+            };
+            let offset = if spread > 0.0 {
+                // Uniform over the disc rather than bunched at its center
+                let radius = spread * self.rng.gen::<f32>().sqrt();
+                Vec2::from_angle(self.rng.gen_range(0.0..std::f32::consts::TAU)) * radius
+            } else {
+                Vec2::ZERO
+            };
+            self.particles[i] = self.new_particle(self.params.emitters[index] + offset);
+        }
+    }
+
+    /// Simulated time per substep, in reference substeps: the build's default
+    /// `steps_per_frame` to a 60 fps frame. Force, friction, velocity and
+    /// lifetimes are all scaled by it, so the motion stays the same when the
+    /// substep count or target frame rate changes, and only how finely it is
+    /// sampled does. 1 at the defaults.
+    pub fn substep_dt(&self) -> f32 {
+        let frame = 60.0 / self.target_fps.max(1.0);
+        frame * build_config::STEPS_PER_FRAME as f32 / self.params.steps_per_frame.max(1) as f32
+    }
+
+    /// Advances every live particle by `steps_per_frame` substeps, recording
+    /// the traced segments for the next `render_into`, then moves z forward.
+    pub fn step(&mut self) {
+        let dt = self.substep_dt();
+        let next = self.next_z_and_hue();
+        let mut params = self.params.modulated(self.frames);
+        params.max_age = (params.max_age as f32 / dt).round() as u32;
+        if params.loop_frames > 0 {
+            // The sweep slows and turns back, so substeps head for where it
+            // is next frame rather than on along `z_step`
+            params.z_step = next[0] - params.z;
+            for (layer, z) in params.layers.iter_mut().zip(&next[1..]) {
+                layer.z_step = z - layer.z;
+            }
+        }
+        if self.steps_on_gpu() {
+            let duotone = palette::duotone(params.color_lo, params.color_hi);
+            let palette = match params.color_mode {
+                ColorMode::Duotone => &duotone[..],
+                ColorMode::Divergence => palette::DIVERGING,
+                _ => &self.palette[..],
+            };
+            let gpu = self.gpu.as_mut().expect("checked by steps_on_gpu");
+            gpu.step(
+                &mut self.particles,
+                &params,
+                dt,
+                palette,
+                self.attractor,
+                self.width,
+                self.height,
+            );
+        } else {
+            if let Some(trace) = &mut self.trace {
+                trace.adopt(&self.particles, self.frames);
+            }
+            self.step_cpu(&params, dt);
+        }
+
+        self.respawn_at_emitters();
+        self.count_density();
+        self.frames += 1;
+        self.particle_high_water = self.particle_high_water.max(self.particles.len());
+        if self.params.compact_every > 0 && self.frames.is_multiple_of(self.params.compact_every as u64) {
+            self.compact_particles();
+        }
+        self.set_z_and_hue(&next);
+    }
+
+    /// Where z, each layer's z and the hue shift go for the next step, in
+    /// that order: on by their `z_step` and `hue_rate`, or with
+    /// `loop_frames` set, along the sweep that brings them back every
+    /// cycle.
+    fn next_z_and_hue(&mut self) -> Vec<f32> {
+        let values: Vec<f32> = std::iter::once(self.params.z)
+            .chain(self.params.layers.iter().map(|l| l.z))
+            .chain(std::iter::once(self.params.hue_shift))
+            .collect();
+        let rates: Vec<f32> = std::iter::once(self.params.z_step)
+            .chain(self.params.layers.iter().map(|l| l.z_step))
+            .chain(std::iter::once(self.params.hue_rate))
+            .collect();
+        let n = self.params.loop_frames as u64;
+        if n == 0 {
+            self.loop_start = None;
+            return values.iter().zip(&rates).map(|(v, rate)| v + rate).collect();
+        }
+        let current = matches!(&self.loop_start, Some((m, r, _)) if *m == n && *r == rates);
+        if !current {
+            // Joining partway through, or a rate changed (e.g. on freezing),
+            // so work back from here to where this cycle would have begun
+            let starts = values.iter().zip(&rates).map(|(v, &rate)| v - loop_offset(rate, self.frames, n)).collect();
+            self.loop_start = Some((n, rates.clone(), starts));
+        }
+        let Some((_, _, starts)) = &self.loop_start else {
+            unreachable!("set above");
+        };
+        starts.iter().zip(&rates).map(|(start, &rate)| start + loop_offset(rate, self.frames + 1, n)).collect()
+    }
+
+    /// Moves z, the layers' z and the hue shift on to `next`, as laid out
+    /// by `next_z_and_hue`.
+    fn set_z_and_hue(&mut self, next: &[f32]) {
+        let mut values = next.iter().copied();
+        self.params.z = values.next().expect("the field's own z comes first");
+        for layer in &mut self.params.layers {
+            layer.z = values.next().expect("one per layer");
+        }
+        self.params.hue_shift = values.next().expect("the hue shift comes last");
+    }
+
+    /// Steps the particles in parallel. `par_extend` keeps their segments
+    /// in particle order, so the serial rasterization after it, and with it
+    /// the frame, is the same whatever the thread count.
+    fn step_cpu(&mut self, params: &Params, dt: f32) {
+        let margin = 10.0;
+        let width_f = self.width as f32;
+        let height_f = self.height as f32;
+        let attractor = self.attractor;
+        let size = Vec2::new(width_f, height_f);
+        let drag = params.friction.powf(dt);
+
+        // Pull toward the cursor and the rest of the acceleration at `p`,
+        // kept apart so Euler adds them in the same order as always
+        let accel = |particle: &Particle, p: Vec2, t: f32| {
+            let cursor = attractor.map_or(Vec2::ZERO, |target| {
+                attraction(p, target, params.attract_strength, params.attract_radius)
+            });
+            let dir = match &self.image_field {
+                Some(field) => Vec2::from_angle(params.field_rotation).rotate(field.dir(p, self.width, self.height)),
+                None => noise_dir(&self.noise, params, warp_domain(params.domain_warp, p, size), t, size),
+            };
+            let pull: Vec2 = params.attractors.iter().map(|a| attractor_force(p, a)).sum();
+            (cursor, (dir * params.force + pull) / particle.mass + params.wind)
+        };
+        let tracer = self.trace.as_ref();
+        let followed = Mutex::new(Vec::new());
+        let trace = |(slot, particle): (usize, &mut Particle)| {
+            let mut traced = Vec::new();
+            let path = tracer.and_then(|t| t.path_at(slot));
+            let mut points = Vec::new();
+            for step in 0..params.steps_per_frame {
+                let prev = particle.pos;
+                let t = step as f32 / params.steps_per_frame as f32;
+                let lookup = warp_domain(params.domain_warp, prev, size);
+                match params.integrator {
+                    Integrator::Euler => {
+                        let (cursor, field) = accel(particle, prev, t);
+                        particle.vel += dt * cursor;
+                        particle.vel += field * dt;
+                    }
+                    Integrator::Midpoint => {
+                        let (cursor, field) = accel(particle, prev, t);
+                        let half_vel = particle.vel + (cursor + field) * dt * 0.5;
+                        let half_t = (step as f32 + 0.5) / params.steps_per_frame as f32;
+                        let (cursor, field) = accel(particle, prev + half_vel * dt * 0.5, half_t);
+                        particle.vel += (cursor + field) * dt;
+                    }
+                }
+                // Semi-implicit: the position moves by the updated velocity
+                particle.vel *= drag;
+                particle.vel = particle.vel.clamp_length_max(params.max_speed);
+                particle.pos += particle.vel * dt;
+                particle.age = particle.age.saturating_add(1);
+
+                let source = match params.color_mode {
+                    ColorMode::Direction => ScalarSource::Direction,
+                    ColorMode::Age => ScalarSource::Age,
+                    ColorMode::Curl => ScalarSource::Curl,
+                    ColorMode::Speed => ScalarSource::Speed,
+                    ColorMode::Divergence => ScalarSource::Divergence,
+                    ColorMode::Palette | ColorMode::Duotone => params.palette_source,
+                };
+                let (c, v) = color_scalar(source, &self.noise, params, particle, lookup, t, size);
+                let v = params.val_min + v * (params.val_max - params.val_min);
+                let v = v * life_taper(particle.age, params.max_age);
+                let color = match params.color_mode {
+                    ColorMode::Palette => palette::sample(&self.palette, c + particle.hue_offset, v),
+                    ColorMode::Divergence => palette::sample(palette::DIVERGING, c + particle.hue_offset, v),
+                    ColorMode::Duotone => palette::sample(
+                        &palette::duotone(params.color_lo, params.color_hi),
+                        c + particle.hue_offset,
+                        v,
+                    ),
+                    // Stop short of wrapping back to red so slow and fast differ
+                    ColorMode::Speed => hsv_to_rgb(c * 0.75 + particle.hue_offset, params.sat, v),
+                    _ => hsv_to_rgb(c + particle.hue_offset, params.sat, v),
+                };
+                let color = smooth_color(&mut particle.color, color, params.color_smoothing);
+
+                let mut crossed = false;
+                let moved = particle.pos;
+                let mut died = particle.age >= params.max_age;
+                // A tile has no edges: what leaves one side comes back on the other
+                let boundary = if params.tileable { BoundaryMode::Wrap } else { params.boundary };
+                match boundary {
+                    BoundaryMode::Kill => {
+                        died |= particle.pos.x < -margin
+                            || particle.pos.x > width_f + margin
+                            || particle.pos.y < -margin
+                            || particle.pos.y > height_f + margin;
+                    }
+                    BoundaryMode::Wrap => crossed = wrap_position(&mut particle.pos, width_f, height_f),
+                    BoundaryMode::Bounce => {
+                        reflect_at_bounds(
+                            &mut particle.pos,
+                            &mut particle.vel,
+                            width_f,
+                            height_f,
+                            params.restitution,
+                        )
+                    }
+                }
+
+                // Don't draw the segment that teleports across the frame, but
+                // on a tile draw it unwrapped and let it wrap as it's drawn
+                let drawn = !crossed || params.tileable;
+                let segment = Segment {
+                    p0: prev,
+                    p1: if crossed { moved } else { particle.pos },
+                    color,
+                    width: 1.0 + params.thickness * particle.vel.length(),
+                };
+                if params.smooth_trails {
+                    // Stale after steps on the GPU or with smooth trails off
+                    if particle.trail.back(0) != prev {
+                        particle.trail = Trail::new(prev);
+                    }
+                    if drawn {
+                        particle.trail.push(segment.p1, params.trail_len as usize);
+                    }
+                    for piece in trail_pieces(&particle.trail, segment, drawn, crossed || died) {
+                        push_symmetric(&mut traced, piece, params.symmetry, size * 0.5);
+                    }
+                    // A wrap teleports, so the curve starts over from here
+                    if crossed {
+                        particle.trail = Trail::new(particle.pos);
+                    }
+                } else if drawn {
+                    push_symmetric(&mut traced, segment, params.symmetry, size * 0.5);
+                }
+                if path.is_some() {
+                    points.push(TracePoint {
+                        frame: self.frames,
+                        substep: step as u32 + 1,
+                        pos: particle.pos,
+                    });
+                }
+
+                if died {
+                    particle.alive = false;
+                    break;
+                }
+            }
+            if path.is_some() {
+                followed.lock().expect("no panics while held").push((slot, points, !particle.alive));
+            }
+            traced
+        };
+        let live = self.particles.par_iter_mut().enumerate().filter(|(_, p)| p.alive);
+        self.segments.par_extend(live.flat_map_iter(trace));
+        if let Some(tracer) = &mut self.trace {
+            for (slot, points, died) in followed.into_inner().expect("no panics while held") {
+                tracer.record(slot, points, died);
+            }
+        }
+    }
+
+    /// Fades `frame` and rasterizes the segments traced since the last call.
+    /// `frame` is RGBA8, `width * height * 4` bytes.
+    pub fn render_into(&mut self, frame: &mut [u8], width: u32, height: u32) {
+        self.apply_fade(frame, width, height, 1);
+        let mut hdr = self.hdr.take();
+        if let Some(hdr) = &mut hdr {
+            self.apply_fade_hdr(hdr, width, height);
+        }
+        self.draw_segments(frame, width, height, 1.0, hdr.as_deref_mut());
+        self.hdr = hdr;
+        if let Some((factor, mut buffer)) = self.supersample.take() {
+            self.apply_fade(&mut buffer, width * factor, height * factor, factor);
+            self.draw_segments(&mut buffer, width * factor, height * factor, factor as f32, None);
+            self.supersample = Some((factor, buffer));
+        }
+        self.segments_drawn = self.segments.len();
+        if let Some((recorded, cap)) = &mut self.recording {
+            let room = cap.saturating_sub(recorded.len());
+            recorded.extend(self.segments.iter().take(room));
+        }
+        self.segments.clear();
+        if let Some(gpu) = &mut self.gpu {
+            self.segments_drawn += gpu.composite(frame, width, height, self.params.blend_mode);
+        }
+    }
+
+    /// Rasterizes the pending segments with coordinates and widths
+    /// multiplied by `scale`, into `hdr` as well when given.
+    fn draw_segments(&self, frame: &mut [u8], width: u32, height: u32, scale: f32, mut hdr: Option<&mut [f32]>) {
+        let draw: raster::DrawFn = if self.params.antialiased {
+            raster::draw_segment_wu
+        } else {
+            raster::draw_segment_bresenham
+        };
+        let blend = raster::Blend {
+            mode: self.params.blend_mode,
+            alpha: self.params.blend_alpha,
+            stroke_alpha: self.params.stroke_alpha.clamp(0.0, 1.0),
+        };
+        let compensation = self.params.density_compensation.max(0.0);
+        let size = Vec2::new(width as f32, height as f32);
+        let lines = self.params.splat_mode != SplatMode::Splats;
+        let splat_radius = (self.params.splat_mode != SplatMode::Lines).then_some(self.params.splat_radius * scale);
+        for seg in self.segments.iter().flat_map(|seg| self.tile_copies(seg, scale, size)) {
+            let (p0, p1) = (seg.p0, seg.p1);
+            let mut color = seg.color;
+            if compensation > 0.0 {
+                let b = brightness_at(frame, width, height, p0);
+                let k = 1.0 / (1.0 + compensation * b);
+                let dim = |c: u8| (c as f32 * k).round() as u8;
+                color = (dim(color.0), dim(color.1), dim(color.2));
+            }
+            if lines {
+                raster::draw_segment_thick(frame, width, height, p0, p1, seg.width, color, blend, draw);
+            }
+            // Each segment ends where the particle is after the substep
+            if let Some(radius) = splat_radius {
+                raster::draw_splat(frame, width, height, p1, radius, color, blend);
+            }
+            if let Some(hdr) = hdr.as_deref_mut() {
+                if lines {
+                    let antialiased = self.params.antialiased;
+                    raster::draw_segment_hdr(hdr, width, height, p0, p1, seg.width, color, blend, antialiased);
+                }
+                if let Some(radius) = splat_radius {
+                    raster::splat_pixels(width, height, p1, radius, |i, coverage| {
+                        blend.apply_hdr(&mut hdr[i * 3..i * 3 + 3], color, coverage)
+                    });
+                }
+            }
+        }
+    }
+
+    /// `seg` scaled to a buffer of `size`, and with `params.tileable` a copy
+    /// shifted a frame over for each edge it runs past, so strokes wrap.
+    fn tile_copies(&self, seg: &Segment, scale: f32, size: Vec2) -> impl Iterator<Item = Segment> {
+        let seg = Segment {
+            p0: seg.p0 * scale,
+            p1: seg.p1 * scale,
+            width: seg.width * scale,
+            ..*seg
+        };
+        let splat = if self.params.splat_mode == SplatMode::Lines { 0.0 } else { self.params.splat_radius * scale };
+        let reach = Vec2::splat(seg.width.max(splat) + 1.0);
+        let (lo, hi) = (seg.p0.min(seg.p1) - reach, seg.p0.max(seg.p1) + reach);
+        let shifts = |lo: f32, hi: f32, side: f32| [(lo < 0.0).then_some(side), Some(0.0), (hi > side).then_some(-side)];
+        let (xs, ys) = if self.params.tileable {
+            (shifts(lo.x, hi.x, size.x), shifts(lo.y, hi.y, size.y))
+        } else {
+            ([None, Some(0.0), None], [None, Some(0.0), None])
+        };
+        ys.into_iter().flatten().flat_map(move |dy| {
+            xs.into_iter().flatten().map(move |dx| {
+                let shift = Vec2::new(dx, dy);
+                Segment {
+                    p0: seg.p0 + shift,
+                    p1: seg.p1 + shift,
+                    ..seg
+                }
+            })
+        })
+    }
+
+    /// `apply_fade` for the HDR copy, lerping straight toward the background
+    /// rather than in linear light, since values there run past 1.
+    fn apply_fade_hdr(&self, hdr: &mut [f32], width: u32, height: u32) {
+        if self.params.fade_mode == FadeMode::Persist || self.background.is_some() {
+            return;
+        }
+        if self.params.fade_mode == FadeMode::Blur {
+            box_blur(hdr, width as usize, height as usize, 3, 1, |v| v, |v| v);
+        }
+        let fade_scales = self.params.fade_rgb().map(|fade| 1.0 - fade);
+        if fade_scales.iter().all(|&s| s >= 1.0) {
+            return;
+        }
+        let (r, g, b) = self.params.bg;
+        let bg = [r, g, b].map(|c| c as f32 / 255.0);
+        for px in hdr.chunks_exact_mut(3) {
+            for ((v, bg), scale) in px.iter_mut().zip(bg).zip(fade_scales) {
+                *v = bg + (*v - bg) * scale.min(1.0);
+            }
+        }
+    }
+
+    /// Fades `frame` per `params.fade_mode`. `scale` is how many frame
+    /// pixels make one canvas pixel, so a supersampled copy blurs as far.
+    fn apply_fade(&self, frame: &mut [u8], width: u32, height: u32, scale: u32) {
+        if self.params.fade_mode == FadeMode::Persist || self.background.is_some() {
+            return;
+        }
+        if self.params.fade_mode == FadeMode::Blur {
+            let radius = scale as usize;
+            box_blur(frame, width as usize, height as usize, 4, radius, f32::from, |v| v.round() as u8);
+        }
+        let fade_scales = self.params.fade_rgb().map(|fade| 1.0 - fade);
+        if fade_scales.iter().all(|&s| s >= 1.0) {
+            return;
+        }
+        let (r, g, b) = self.params.bg;
+        // A channel that isn't fading is left alone, not stepped down
+        let lut = |scale: f32, target: u8| {
+            if scale >= 1.0 {
+                std::array::from_fn(|c| c as u8)
+            } else {
+                color::fade_toward_lut(scale, target)
+            }
+        };
+        let luts = [lut(fade_scales[0], r), lut(fade_scales[1], g), lut(fade_scales[2], b)];
+        for px in frame.chunks_exact_mut(4) {
+            px[0] = luts[0][px[0] as usize];
+            px[1] = luts[1][px[1] as usize];
+            px[2] = luts[2][px[2] as usize];
+            px[3] = 255;
+        }
+    }
+
+    /// The presentation-only passes, vignette then gamma, for the shown and
+    /// exported frame; the accumulating canvas should never get them.
+    pub fn apply_post_effects(&self, frame: &mut [u8], width: u32, height: u32) {
+        self.apply_vignette(frame, width, height);
+        self.apply_gamma(frame);
+    }
+
+    /// Whether `apply_post_effects` changes anything.
+    pub fn has_post_effects(&self) -> bool {
+        self.params.vignette > 0.0 || self.params.gamma != 1.0
+    }
+
+    /// Applies `params.gamma` to every color channel of `frame`.
+    pub fn apply_gamma(&self, frame: &mut [u8]) {
+        if self.params.gamma == 1.0 {
+            return;
+        }
+        let lut = color::gamma_lut(self.params.gamma);
+        for px in frame.chunks_exact_mut(4) {
+            px[0] = lut[px[0] as usize];
+            px[1] = lut[px[1] as usize];
+            px[2] = lut[px[2] as usize];
+        }
+    }
+
+    /// Darkens `frame` toward its corners by `params.vignette`, scaling by
+    /// `1 - vignette * r^2` in linear light, where `r` is the distance from
+    /// the center relative to the corner distance.
+    pub fn apply_vignette(&self, frame: &mut [u8], width: u32, height: u32) {
+        let strength = self.params.vignette.clamp(0.0, 1.0);
+        if strength <= 0.0 {
+            return;
+        }
+        // Quantize the falloff so each level needs only one lookup table
+        const LEVELS: usize = 128;
+        let luts: Vec<[u8; 256]> = (0..LEVELS)
+            .map(|i| color::scale_lut(i as f32 / (LEVELS - 1) as f32))
+            .collect();
+        let center = Vec2::new(width as f32, height as f32) * 0.5;
+        let inv_corner_sq = 1.0 / center.length_squared().max(1.0);
+        for (i, px) in frame.chunks_exact_mut(4).enumerate() {
+            let p = Vec2::new((i as u32 % width) as f32 + 0.5, (i as u32 / width) as f32 + 0.5);
+            let r_sq = (p - center).length_squared() * inv_corner_sq;
+            let factor = (1.0 - strength * r_sq).clamp(0.0, 1.0);
+            let lut = &luts[(factor * (LEVELS - 1) as f32).round() as usize];
+            px[0] = lut[px[0] as usize];
+            px[1] = lut[px[1] as usize];
+            px[2] = lut[px[2] as usize];
+        }
+    }
+
+    /// The field at the current z slice as an image no wider or taller than
+    /// `max_side`, shaped like the canvas: hue is the flow direction (red
+    /// pointing right, turning through yellow as it points down), black where
+    /// there is none.
+    pub fn field_image(&self, max_side: u32) -> image::RgbaImage {
+        let shrink = max_side as f32 / self.width.max(self.height) as f32;
+        let width = ((self.width as f32 * shrink).round() as u32).max(1);
+        let height = ((self.height as f32 * shrink).round() as u32).max(1);
+        let step = Vec2::new(self.width as f32 / width as f32, self.height as f32 / height as f32);
+        let params = self.params.modulated(self.frames);
+        image::RgbaImage::from_fn(width, height, |x, y| {
+            let dir = self.field_dir_with(&params, (Vec2::new(x as f32, y as f32) + 0.5) * step);
+            let hue = dir.y.atan2(dir.x) / std::f32::consts::TAU;
+            let value = if dir == Vec2::ZERO { 0.0 } else { 0.9 };
+            let (r, g, b) = color::hsv_to_rgb(hue, 0.8, value);
+            image::Rgba([r, g, b, 255])
+        })
+    }
+
+    /// Draws the flow-field quiver: one short arrow per grid cell showing
+    /// the field direction at the current z slice.
+    pub fn draw_field(&self, frame: &mut [u8], width: u32, height: u32) {
+        let spacing = 24.0;
+        let half_len = spacing * 0.4;
+        let color = (70, 70, 70);
+        let params = self.params.modulated(self.frames);
+        let mut y = spacing * 0.5;
+        while y < height as f32 {
+            let mut x = spacing * 0.5;
+            while x < width as f32 {
+                let center = Vec2::new(x, y);
+                let dir = self.field_dir_with(&params, center);
+                let tail = center - dir * half_len;
+                let tip = center + dir * half_len;
+                raster::draw_segment_bresenham(frame, width, height, tail, tip, color, raster::Blend::ADDITIVE);
+                for side in [-1.0f32, 1.0] {
+                    let barb = Vec2::from_angle(side * 2.6).rotate(dir) * (half_len * 0.5);
+                    raster::draw_segment_bresenham(frame, width, height, tip, tip + barb, color, raster::Blend::ADDITIVE);
+                }
+                x += spacing;
+            }
+            y += spacing;
+        }
+    }
+}
+
+/// Eases `color` from the particle's running color `held`, keeping
+/// `smoothing` of the old one, and stores the result in `held`. 0 passes
+/// colors through untouched.
+fn smooth_color(held: &mut Option<Vec3>, color: (u8, u8, u8), smoothing: f32) -> (u8, u8, u8) {
+    if smoothing <= 0.0 {
+        return color;
+    }
+    let new = Vec3::new(color.0 as f32, color.1 as f32, color.2 as f32);
+    let eased = held.map_or(new, |old| new.lerp(old, smoothing.min(1.0)));
+    *held = Some(eased);
+    let eased = eased.round();
+    (eased.x as u8, eased.y as u8, eased.z as u8)
+}
+
+/// Position of the `i`th of `count` particles spawned this frame on a canvas
+/// of `size`.
+fn spawn_position(mode: SpawnMode, i: usize, count: usize, size: Vec2, rng: &mut ChaCha12Rng) -> Vec2 {
+    let t = i as f32 / count as f32;
+    match mode {
+        SpawnMode::Line => {
+            let jitter = rng.gen_range(-0.5f32..0.5f32);
+            Vec2::new(size.x * 0.5, t * size.y + jitter)
+        }
+        SpawnMode::Uniform => Vec2::new(rng.gen_range(0.0..size.x), rng.gen_range(0.0..size.y)),
+        SpawnMode::Grid => {
+            // Roughly square cells: cols / rows matches the frame's aspect
+            let cols = ((count as f32 * size.x / size.y).sqrt().ceil() as usize).max(1);
+            let rows = count.div_ceil(cols);
+            let cell = Vec2::new(size.x / cols as f32, size.y / rows as f32);
+            let jitter = Vec2::new(rng.gen_range(-0.5f32..0.5f32), rng.gen_range(-0.5f32..0.5f32));
+            Vec2::new((i % cols) as f32 + 0.5, (i / cols) as f32 + 0.5) * cell + jitter
+        }
+        SpawnMode::Circle => {
+            let radius = size.min_element() * 0.35;
+            let angle = (t + rng.gen_range(-0.5f32..0.5f32) / count as f32) * std::f32::consts::TAU;
+            size * 0.5 + Vec2::from_angle(angle) * radius
+        }
+        SpawnMode::Edges => {
+            // Walk the border clockwise from the top-left corner
+            let perimeter = 2.0 * (size.x + size.y);
+            let d = ((t + rng.gen_range(0.0f32..1.0f32) / count as f32) * perimeter) % perimeter;
+            if d < size.x {
+                Vec2::new(d, 0.0)
+            } else if d < size.x + size.y {
+                Vec2::new(size.x, d - size.x)
+            } else if d < 2.0 * size.x + size.y {
+                Vec2::new(2.0 * size.x + size.y - d, size.y)
+            } else {
+                Vec2::new(0.0, perimeter - d)
+            }
+        }
+    }
+}
+
+/// Normalized scalar `t` (0..=1) and brightness `v` for a segment, per source.
+fn color_scalar(
+    source: ScalarSource,
+    noise: &FlowNoise,
+    params: &Params,
+    particle: &Particle,
+    lookup: Vec2,
+    frame_t: f32,
+    size: Vec2,
+) -> (f32, f32) {
+    match source {
+        ScalarSource::Direction => {
+            let angle = particle.vel.y.atan2(particle.vel.x);
+            let mut hue = (angle / std::f32::consts::TAU).fract();
+            if hue < 0.0 {
+                hue += 1.0;
+            }
+            let speed = particle.vel.length();
+            let v = (speed * 0.5).clamp(0.1, 1.0);
+            ((hue + params.hue_shift).fract(), v)
+        }
+        ScalarSource::Age => {
+            let cycle = ((particle.age as f32) * 0.002).fract();
+            let hue = (easing::ease(params.age_easing, cycle) + params.hue_shift).fract();
+            let v = (particle.vel.length() * 0.5).clamp(0.1, 1.0);
+            (hue, v)
+        }
+        ScalarSource::Curl => {
+            let eps = 2.0;
+            let a0 = noise_angle(noise, params, lookup, frame_t, size);
+            let a1 = noise_angle(noise, params, lookup + Vec2::new(eps, 0.0), frame_t, size);
+            let mut da = a1 - a0;
+            while da > std::f32::consts::PI {
+                da -= std::f32::consts::TAU;
+            }
+            while da < -std::f32::consts::PI {
+                da += std::f32::consts::TAU;
+            }
+            let hue = (da.abs() / std::f32::consts::PI).clamp(0.0, 1.0);
+            let v = (particle.vel.length() * 0.6).clamp(0.2, 1.0);
+            (hue, v)
+        }
+        ScalarSource::Speed => {
+            let t = (particle.vel.length() / params.speed_ref.max(f32::EPSILON)).clamp(0.0, 1.0);
+            (t, 1.0)
+        }
+        ScalarSource::Divergence => {
+            let eps = 2.0;
+            let dir = |d: Vec2| noise_dir(noise, params, lookup + d, frame_t, size);
+            let (ex, ey) = (Vec2::new(eps, 0.0), Vec2::new(0.0, eps));
+            let div = (dir(ex).x - dir(-ex).x + dir(ey).y - dir(-ey).y) / (2.0 * eps);
+            // Relative to how fast the direction turns at the base frequency
+            let rate = params.scale() * std::f32::consts::TAU;
+            let t = 0.5 + 0.5 * (div / rate.max(f32::EPSILON)).clamp(-1.0, 1.0);
+            let v = (particle.vel.length() * 0.6).clamp(0.2, 1.0);
+            (t, v)
+        }
+    }
+}
+
+/// Brightest channel of the pixel under `p` (clamped into the frame), in
+/// 0..=1.
+fn brightness_at(frame: &[u8], width: u32, height: u32, p: Vec2) -> f32 {
+    let x = (p.x.max(0.0) as u32).min(width - 1);
+    let y = (p.y.max(0.0) as u32).min(height - 1);
+    let i = ((y * width + x) * 4) as usize;
+    frame[i].max(frame[i + 1]).max(frame[i + 2]) as f32 / 255.0
+}
+
+/// The curve pieces `trail` has ready after a substep, in `segment`'s color
+/// and width. A span's curve needs the point after it, so with a point just
+/// `pushed` the span before the newest is drawn, and with the trail `ending`
+/// the newest span too, leaning on nothing past its end.
+fn trail_pieces(trail: &Trail, segment: Segment, pushed: bool, ending: bool) -> impl Iterator<Item = Segment> {
+    let back = |i| trail.back(i);
+    let spans = [
+        (pushed && trail.len >= 3).then(|| [back(3), back(2), back(1), back(0)]),
+        (ending && trail.len >= 2).then(|| [back(2), back(1), back(0), back(0)]),
+    ];
+    spans
+        .into_iter()
+        .flatten()
+        .flat_map(move |[before, p0, p1, after]| curve_pieces(before, Segment { p0, p1, ..segment }, after))
+}
+
+/// Splits `segment` into short straight pieces along the Catmull-Rom curve
+/// through `before`, its endpoints and `after`, about two pixels apiece.
+fn curve_pieces(before: Vec2, segment: Segment, after: Vec2) -> impl Iterator<Item = Segment> {
+    let (p0, p1, p2, p3) = (before, segment.p0, segment.p1, after);
+    let at = move |t: f32| {
+        let (t2, t3) = (t * t, t * t * t);
+        0.5 * (2.0 * p1
+            + (p2 - p0) * t
+            + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+            + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+    };
+    let pieces = ((p2 - p1).length() / 2.0).ceil().clamp(1.0, 16.0) as usize;
+    (0..pieces).map(move |i| Segment {
+        p0: at(i as f32 / pieces as f32),
+        p1: at((i + 1) as f32 / pieces as f32),
+        ..segment
+    })
+}
+
+/// Pushes `segment`, or with `symmetry` above 1 its images under each of the
+/// rotations by multiples of `TAU / symmetry` about `center` and their
+/// reflections across the horizontal axis.
+fn push_symmetric(out: &mut Vec<Segment>, segment: Segment, symmetry: u32, center: Vec2) {
+    if symmetry <= 1 {
+        out.push(segment);
+        return;
+    }
+    for k in 0..symmetry {
+        let rotation = Vec2::from_angle(std::f32::consts::TAU * k as f32 / symmetry as f32);
+        for mirror in [1.0, -1.0] {
+            let map = |p: Vec2| {
+                let d = p - center;
+                center + rotation.rotate(Vec2::new(d.x, d.y * mirror))
+            };
+            out.push(Segment {
+                p0: map(segment.p0),
+                p1: map(segment.p1),
+                ..segment
+            });
+        }
+    }
+}
+
+/// An HDR copy of the RGBA8 `frame`, in units where 1 is a full channel.
+fn hdr_from(frame: &[u8]) -> Vec<f32> {
+    frame
+        .chunks_exact(4)
+        .flat_map(|px| px[..3].iter().map(|&c| c as f32 / 255.0))
+        .collect()
+}
+
+/// Brightness multiplier that eases from 1 to 0 over the last fifth of a
+/// particle's life.
+fn life_taper(age: u32, max_age: u32) -> f32 {
+    let remaining = 1.0 - age as f32 / max_age.max(1) as f32;
+    let x = (remaining / 0.2).clamp(0.0, 1.0);
+    x * x * (3.0 - 2.0 * x)
+}
+
+/// How far past the start of its cycle a value moving `rate` a frame (a z
+/// by its `z_step`, or the hue shift) is after `frame` frames of an `n`
+/// frame loop: a cosine out and back, 0 at each end of the cycle and
+/// `n * rate / 2` halfway.
+fn loop_offset(rate: f32, frame: u64, n: u64) -> f32 {
+    let phase = (frame % n) as f32 / n as f32;
+    rate * n as f32 * 0.25 * (1.0 - (phase * std::f32::consts::TAU).cos())
+}
+
+/// Wraps `pos` onto the torus `[0, width) x [0, height)`. Returns whether it
+/// crossed an edge.
+fn wrap_position(pos: &mut Vec2, width: f32, height: f32) -> bool {
+    let wrapped = Vec2::new(pos.x.rem_euclid(width), pos.y.rem_euclid(height));
+    let crossed = wrapped != *pos;
+    *pos = wrapped;
+    crossed
+}
+
+/// Mirrors `pos` back inside the frame and flips the velocity component
+/// that carried it out, scaling it by `restitution`.
+fn reflect_at_bounds(pos: &mut Vec2, vel: &mut Vec2, width: f32, height: f32, restitution: f32) {
+    let max_x = width - 1.0;
+    let max_y = height - 1.0;
+    if pos.x < 0.0 {
+        pos.x = -pos.x;
+        vel.x = -vel.x * restitution;
+    } else if pos.x > max_x {
+        pos.x = 2.0 * max_x - pos.x;
+        vel.x = -vel.x * restitution;
+    }
+    if pos.y < 0.0 {
+        pos.y = -pos.y;
+        vel.y = -vel.y * restitution;
+    } else if pos.y > max_y {
+        pos.y = 2.0 * max_y - pos.y;
+        vel.y = -vel.y * restitution;
+    }
+    // A very fast particle can overshoot even after mirroring
+    pos.x = pos.x.clamp(0.0, max_x);
+    pos.y = pos.y.clamp(0.0, max_y);
+}
+
+/// Pull toward `target` that fades out with distance; strongest within `radius`.
+fn attraction(pos: Vec2, target: Vec2, strength: f32, radius: f32) -> Vec2 {
+    let delta = target - pos;
+    let dist = delta.length();
+    if dist < 1.0 {
+        return Vec2::ZERO;
+    }
+    let falloff = radius / (radius + dist);
+    delta / dist * strength * falloff * falloff
+}
+
+/// Replaces the first three channels of each `stride`-wide pixel of `data`
+/// with their mean over the `2 * radius + 1` square around it, clamped at
+/// the edges. Done as a horizontal then a vertical pass.
+fn box_blur<T: Copy>(
+    data: &mut [T],
+    width: usize,
+    height: usize,
+    stride: usize,
+    radius: usize,
+    to_f32: impl Fn(T) -> f32,
+    from_f32: impl Fn(f32) -> T,
+) {
+    if width == 0 || height == 0 || data.len() < width * height * stride {
+        return;
+    }
+    let norm = 1.0 / (2 * radius + 1) as f32;
+    let mut rows = vec![0.0f32; width * height * 3];
+    for y in 0..height {
+        for x in 0..width {
+            for c in 0..3 {
+                let sum: f32 = (x as isize - radius as isize..=(x + radius) as isize)
+                    .map(|sx| to_f32(data[(y * width + sx.clamp(0, width as isize - 1) as usize) * stride + c]))
+                    .sum();
+                rows[(y * width + x) * 3 + c] = sum * norm;
+            }
+        }
+    }
+    for y in 0..height {
+        for x in 0..width {
+            for c in 0..3 {
+                let sum: f32 = (y as isize - radius as isize..=(y + radius) as isize)
+                    .map(|sy| rows[(sy.clamp(0, height as isize - 1) as usize * width + x) * 3 + c])
+                    .sum();
+                data[(y * width + x) * stride + c] = from_f32(sum * norm);
+            }
+        }
+    }
+}
+
+/// Inverse-square pull toward `a.pos`, or push away when its strength is
+/// negative. Inside `a.radius` it holds at `a.strength` rather than blowing
+/// up near the center.
+fn attractor_force(pos: Vec2, a: &Attractor) -> Vec2 {
+    let delta = a.pos - pos;
+    let dist = delta.length();
+    if dist < 1e-3 {
+        return Vec2::ZERO;
+    }
+    let radius = a.radius.max(1.0);
+    let falloff = radius / dist.max(radius);
+    delta / dist * a.strength * falloff * falloff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::ImageEncoder;
+
+    /// Runs a seeded simulation on a pool of `threads` threads and returns
+    /// its last frame encoded as PNG.
+    fn render_png(threads: usize) -> Vec<u8> {
+        let (w, h) = (160, 120);
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+        let frame = pool.install(|| {
+            let mut params = Params::new(h);
+            params.spawn_mode = SpawnMode::Uniform;
+            params.spawn_count = 400;
+            params.steps_per_frame = 40;
+            let mut sim = Simulator::new(w, h, params, Seeds::from_u64(7));
+            let mut frame = vec![0u8; (w * h * 4) as usize];
+            sim.clear(&mut frame);
+            for _ in 0..5 {
+                sim.spawn();
+                sim.step();
+                sim.render_into(&mut frame, w, h);
+            }
+            frame
+        });
+        let mut png = Vec::new();
+        image::codecs::png::PngEncoder::new(&mut png)
+            .write_image(&frame, w, h, image::ColorType::Rgba8)
+            .unwrap();
+        png
+    }
+
+    #[test]
+    fn output_is_independent_of_thread_count() {
+        assert_eq!(render_png(1), render_png(4));
+    }
+
+    #[test]
+    fn loop_offset_returns_to_zero_each_cycle() {
+        let n = 24;
+        assert_eq!(loop_offset(0.01, n, n), loop_offset(0.01, 0, n));
+        assert_eq!(loop_offset(0.01, 0, n), 0.0);
+    }
+
+    #[test]
+    fn loop_frames_bring_z_and_hue_back() {
+        let mut params = Params::new(40);
+        params.loop_frames = 24;
+        params.lfo = true;
+        params.layers.push(NoiseLayer {
+            scale: 0.02,
+            z: 3.0,
+            z_step: 0.004,
+            weight: 0.5,
+        });
+        let mut sim = Simulator::new(40, 40, params, Seeds::from_u64(7));
+        let start = sim.params.modulated(0);
+        for _ in 0..24 {
+            sim.step();
+        }
+        assert_eq!(sim.params.z, start.z);
+        assert_eq!(sim.params.layers[0].z, start.layers[0].z);
+        assert_eq!(sim.params.hue_shift, start.hue_shift);
+        let end = sim.params.modulated(24);
+        assert_eq!((end.force, end.scale_x), (start.force, start.scale_x));
+    }
+
+    #[test]
+    fn zeroed_rates_hold_z_and_hue_partway_through_a_loop() {
+        let mut params = Params::new(40);
+        params.loop_frames = 24;
+        let mut sim = Simulator::new(40, 40, params, Seeds::from_u64(7));
+        for _ in 0..5 {
+            sim.step();
+        }
+        sim.params.z_step = 0.0;
+        sim.params.hue_rate = 0.0;
+        let (z, hue) = (sim.params.z, sim.params.hue_shift);
+        sim.step();
+        assert_eq!((sim.params.z, sim.params.hue_shift), (z, hue));
+    }
+
+    #[test]
+    fn trail_keeps_the_latest_positions_up_to_its_cap() {
+        let mut trail = Trail::new(Vec2::ZERO);
+        for i in 1..=MAX_TRAIL_LEN + 3 {
+            trail.push(Vec2::splat(i as f32), 4);
+        }
+        let newest = (MAX_TRAIL_LEN + 3) as f32;
+        assert_eq!(trail.back(0), Vec2::splat(newest));
+        assert_eq!(trail.back(3), Vec2::splat(newest - 3.0));
+        assert_eq!(trail.back(9), Vec2::splat(newest - 3.0));
+    }
+
+    #[test]
+    fn trickled_spawns_cover_the_canvas_height() {
+        let (w, h) = (160, 120);
+        let mut params = Params::new(h);
+        params.spawn_mode = SpawnMode::Line;
+        params.spawn_count = 60;
+        let mut sim = Simulator::new(w, h, params, Seeds::from_u64(7));
+        for _ in 0..60 {
+            sim.spawn_trickle(1);
+        }
+        let mut rows: Vec<u32> = sim.particles.iter().map(|p| p.pos.y.max(0.0) as u32).collect();
+        rows.sort_unstable();
+        rows.dedup();
+        assert!(rows.len() >= 50, "only {} distinct rows", rows.len());
+        assert!(rows[0] < h / 10 && rows[rows.len() - 1] > h * 9 / 10);
+    }
+}
@@ -1,15 +1,26 @@
 #![cfg_attr(target_os = "windows", windows_subsystem = "windows")]
 
-use anyhow::Result;
+mod gui;
+mod keymap;
+
+use anyhow::{bail, Context, Result};
 use glam::Vec2;
-use image::{ImageBuffer, Rgba};
-use noise::{NoiseFn, Perlin};
-use pixels::{Pixels, SurfaceTexture};
-use rand::{rngs::StdRng, Rng, SeedableRng};
+use gui::Gui;
+use keymap::{Action, Keymap, KEYMAP_PATH};
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, ImageBuffer, Rgba, RgbaImage};
+use pixels::{Pixels, PixelsBuilder, SurfaceTexture};
+use rust_color_visuals::keyframes::Keyframes;
+use rust_color_visuals::{
+    build_config, font, palette, AgeEasing, BlendMode, BoundaryMode, Checkpoint, ColorMode, DomainWarp, FadeMode, FieldMode, ImageField, Integrator, NoiseKind, NoiseLayer, Params, Seeds,
+    Simulator, SpawnBias, SpawnMode, SplatMode,
+};
 use winit::dpi::LogicalSize;
-use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
+use winit::event::{
+    ElementState, Event, KeyboardInput, ModifiersState, MouseButton, WindowEvent,
+};
 use winit::event_loop::{ControlFlow, EventLoop};
-use winit::window::{Fullscreen, WindowBuilder};
+use winit::window::{Fullscreen, Window, WindowBuilder};
 
 // No explicit Windows-specific builder trait needed for parent embedding in this winit version.
 
@@ -20,15 +31,19 @@ use windows_sys::Win32::{
 };
 
 use std::env;
-use std::time::{Duration, Instant};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-// Build-time configuration values generated by build.rs (written into src/)
-mod build_config;
 
 const WIDTH: u32 = build_config::WIDTH;
 const HEIGHT: u32 = build_config::HEIGHT;
 
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(not(windows), allow(dead_code))]
 enum ScreenSaverMode {
     Normal,
     Screensaver,
@@ -48,442 +63,2400 @@ fn parse_screensaver_mode() -> ScreenSaverMode {
         if al == "/s" || al == "-s" {
             return ScreenSaverMode::Screensaver;
         }
-        if al == "/c" || al == "-c" || al.starts_with("/c:") || al.starts_with("-c:") {
-            return ScreenSaverMode::Config;
+        if al == "/c" || al == "-c" || al.starts_with("/c:") || al.starts_with("-c:") {
+            return ScreenSaverMode::Config;
+        }
+        if al.starts_with("/p:") || al.starts_with("-p:") {
+            let handle_str = al.split_once(':').map(|(_, r)| r.to_string()).unwrap_or_default();
+            if let Ok(h) = handle_str.trim().parse::<isize>() {
+                return ScreenSaverMode::Preview(h);
+            }
+            return ScreenSaverMode::Preview(0);
+        }
+        if al == "/p" || al == "-p" {
+            let hwnd = args.get(i + 1).cloned().unwrap_or_else(|| "0".to_string());
+            if let Ok(h) = hwnd.trim().parse::<isize>() {
+                return ScreenSaverMode::Preview(h);
+            }
+            return ScreenSaverMode::Preview(0);
+        }
+        i += 1;
+    }
+    ScreenSaverMode::Normal
+}
+
+/// Options given as `--flag value` pairs on the command line, independent of the
+/// screensaver switches above.
+#[derive(Clone, Debug)]
+struct CliOptions {
+    /// Render this many frames without a window, then exit.
+    headless_frames: Option<u64>,
+    /// In headless mode, save a PNG every N frames.
+    save_every: u64,
+    /// Time this many frames without a window or saving, then report.
+    bench_frames: Option<u64>,
+    /// Wait for the display's refresh when presenting.
+    vsync: bool,
+    /// In the window, save a PNG every N rendered frames.
+    autosave_every: Option<u64>,
+    /// Close the window once this many frames have been rendered.
+    max_frames: Option<u64>,
+    /// Frames simulated and drawn before the first one is shown or saved.
+    warmup: u64,
+    gif: GifSettings,
+    /// TOML file of `Params` overriding the build-time defaults.
+    config: Option<String>,
+    /// JSON of per-frame partial params a headless render eases between.
+    keyframes: Option<String>,
+    width: u32,
+    height: u32,
+    fullscreen: bool,
+    /// Seeds both the noise field and the particle RNG.
+    seed: Option<u64>,
+    /// Frame rate cap; uncapped when absent.
+    fps: Option<u32>,
+    /// Grayscale image whose gradient replaces the noise field.
+    field_image: Option<String>,
+    /// Follow the image's contours rather than its gradient.
+    field_contour: bool,
+    /// Base directory under which each run gets its own timestamped folder.
+    outdir: String,
+    /// Stream every rendered frame to this MP4 file through ffmpeg.
+    record_mp4: Option<String>,
+    /// Append per-frame timings to this CSV file.
+    profile: Option<String>,
+    /// Render at this multiple of the window size, downsampled for display.
+    render_scale: u32,
+    /// Fixed internal resolution, independent of the window; overrides
+    /// `render_scale`.
+    render_size: Option<(u32, u32)>,
+    /// Step particles with the wgpu compute shader when it can.
+    gpu: bool,
+    /// Resume from a state file written with F5.
+    load_state: Option<String>,
+    /// Image to extract an extra palette from.
+    palette_from: Option<String>,
+    /// Shadow and highlight colors to start in `ColorMode::Duotone` with.
+    duotone: Option<[(u8, u8, u8); 2]>,
+    /// Image the canvas starts from instead of the background color.
+    background_image: Option<String>,
+    /// Record segments for an SVG from the first frame.
+    record_svg: bool,
+    /// Segment cap for SVG recordings.
+    svg_max_segments: usize,
+    /// Follow this many particles and write their paths out on exit.
+    trace: Option<usize>,
+    /// Supersampling factor for saved PNGs; 1 is off.
+    ss: u32,
+    /// Keep an unclipped float copy of the frame and save it alongside each
+    /// PNG as a 16-bit one.
+    hdr: bool,
+    /// Save a thumbnail of the flow field with each PNG.
+    save_field: bool,
+    /// Render a seamless tile (`Params::tileable`).
+    tileable: bool,
+    /// Show the egui control panel over the window.
+    gui: bool,
+    /// Run a headless sweep of this many frames per parameter combination.
+    sweep_frames: Option<u64>,
+    /// `scale` and `force` values to sweep; empty keeps the configured one.
+    sweep_scale: Vec<f32>,
+    sweep_force: Vec<f32>,
+}
+
+impl Default for CliOptions {
+    fn default() -> Self {
+        Self {
+            headless_frames: None,
+            save_every: 1,
+            bench_frames: None,
+            vsync: true,
+            autosave_every: None,
+            max_frames: None,
+            warmup: 0,
+            gif: GifSettings::default(),
+            config: None,
+            width: WIDTH,
+            height: HEIGHT,
+            fullscreen: false,
+            seed: None,
+            fps: None,
+            field_image: None,
+            field_contour: false,
+            outdir: "out".to_string(),
+            record_mp4: None,
+            profile: None,
+            render_scale: 1,
+            render_size: None,
+            gpu: false,
+            load_state: None,
+            keyframes: None,
+            palette_from: None,
+            duotone: None,
+            background_image: None,
+            record_svg: false,
+            svg_max_segments: 200_000,
+            trace: None,
+            ss: 1,
+            hdr: false,
+            save_field: false,
+            tileable: false,
+            gui: false,
+            sweep_frames: None,
+            sweep_scale: Vec::new(),
+            sweep_force: Vec::new(),
+        }
+    }
+}
+
+fn parse_cli_options() -> Result<CliOptions> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut opts = CliOptions::default();
+
+    let mut i = 0usize;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--headless" => {
+                opts.headless_frames = Some(parse_flag_value(&args, i)?);
+                i += 1;
+            }
+            "--bench" => {
+                opts.bench_frames = Some(parse_flag_value::<u64>(&args, i)?.max(1));
+                i += 1;
+            }
+            "--vsync" => opts.vsync = true,
+            "--no-vsync" => opts.vsync = false,
+            "--save-every" => {
+                opts.save_every = parse_flag_value::<u64>(&args, i)?.max(1);
+                i += 1;
+            }
+            "--warmup" => {
+                opts.warmup = parse_flag_value(&args, i)?;
+                i += 1;
+            }
+            "--frames" => {
+                opts.max_frames = Some(parse_flag_value::<u64>(&args, i)?.max(1));
+                i += 1;
+            }
+            "--autosave-every" => {
+                opts.autosave_every = Some(parse_flag_value::<u64>(&args, i)?.max(1));
+                i += 1;
+            }
+            "--width" => {
+                opts.width = parse_flag_value::<u32>(&args, i)?.max(1);
+                i += 1;
+            }
+            "--height" => {
+                opts.height = parse_flag_value::<u32>(&args, i)?.max(1);
+                i += 1;
+            }
+            "--fullscreen" => opts.fullscreen = true,
+            "--background-image" => {
+                opts.background_image = Some(parse_flag_value(&args, i)?);
+                i += 1;
+            }
+            "--sweep" => {
+                opts.sweep_frames = Some(parse_flag_value::<u64>(&args, i)?.max(1));
+                i += 1;
+            }
+            "--sweep-scale" => {
+                opts.sweep_scale = parse_flag_list(&args, i)?;
+                i += 1;
+            }
+            "--sweep-force" => {
+                opts.sweep_force = parse_flag_list(&args, i)?;
+                i += 1;
+            }
+            "--ss" => {
+                opts.ss = parse_flag_value::<u32>(&args, i)?.clamp(1, 4);
+                i += 1;
+            }
+            "--render-scale" => {
+                opts.render_scale = parse_flag_value::<u32>(&args, i)?.clamp(1, 4);
+                i += 1;
+            }
+            "--render-size" => {
+                let raw: String = parse_flag_value(&args, i)?;
+                let size = raw
+                    .split_once('x')
+                    .and_then(|(w, h)| Some((w.trim().parse::<u32>().ok()?, h.trim().parse::<u32>().ok()?)))
+                    .filter(|&(w, h)| w > 0 && h > 0)
+                    .ok_or_else(|| anyhow::anyhow!("invalid value for --render-size: {} (expected WxH)", raw))?;
+                opts.render_size = Some(size);
+                i += 1;
+            }
+            "--seed" => {
+                opts.seed = Some(parse_flag_value(&args, i)?);
+                i += 1;
+            }
+            "--fps" => {
+                opts.fps = Some(parse_flag_value::<u32>(&args, i)?.max(1));
+                i += 1;
+            }
+            "--field-image" => {
+                opts.field_image = Some(parse_flag_value(&args, i)?);
+                i += 1;
+            }
+            "--field-contour" => opts.field_contour = true,
+            "--gpu" => opts.gpu = true,
+            "--palette-from" => {
+                opts.palette_from = Some(parse_flag_value(&args, i)?);
+                i += 1;
+            }
+            "--duotone" => {
+                let raw: String = parse_flag_value(&args, i)?;
+                let (lo, hi) = raw
+                    .split_once(',')
+                    .ok_or_else(|| anyhow::anyhow!("--duotone expects two colors, got {}", raw))?;
+                opts.duotone = Some([parse_hex_color(lo)?, parse_hex_color(hi)?]);
+                i += 1;
+            }
+            "--record-svg" => opts.record_svg = true,
+            "--hdr" => opts.hdr = true,
+            "--save-field" => opts.save_field = true,
+            "--tileable" => opts.tileable = true,
+            "--gui" => opts.gui = true,
+            "--svg-max-segments" => {
+                opts.svg_max_segments = parse_flag_value::<usize>(&args, i)?.max(1);
+                i += 1;
+            }
+            "--trace" => {
+                opts.trace = Some(parse_flag_value::<usize>(&args, i)?.max(1));
+                i += 1;
+            }
+            "--keyframes" => {
+                opts.keyframes = Some(parse_flag_value(&args, i)?);
+                i += 1;
+            }
+            "--load-state" => {
+                opts.load_state = Some(parse_flag_value(&args, i)?);
+                i += 1;
+            }
+            "--outdir" => {
+                opts.outdir = parse_flag_value(&args, i)?;
+                i += 1;
+            }
+            "--config" => {
+                opts.config = Some(parse_flag_value(&args, i)?);
+                i += 1;
+            }
+            "--record-mp4" => {
+                opts.record_mp4 = Some(parse_flag_value(&args, i)?);
+                i += 1;
+            }
+            "--profile" => {
+                opts.profile = Some(parse_flag_value(&args, i)?);
+                i += 1;
+            }
+            "--record-path" => {
+                opts.gif.path = parse_flag_value(&args, i)?;
+                i += 1;
+            }
+            "--record-skip" => {
+                opts.gif.frame_skip = parse_flag_value::<u64>(&args, i)?.max(1);
+                i += 1;
+            }
+            "--record-max-frames" => {
+                opts.gif.max_frames = parse_flag_value::<usize>(&args, i)?.max(1);
+                i += 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    Ok(opts)
+}
+
+fn parse_flag_value<T: std::str::FromStr>(args: &[String], i: usize) -> Result<T> {
+    let flag = &args[i];
+    let raw = args
+        .get(i + 1)
+        .ok_or_else(|| anyhow::anyhow!("{} expects a value", flag))?;
+    raw.trim()
+        .parse()
+        .map_err(|_| anyhow::anyhow!("invalid value for {}: {}", flag, raw))
+}
+
+/// Parses the comma-separated numbers following the flag at `args[i]`.
+fn parse_flag_list(args: &[String], i: usize) -> Result<Vec<f32>> {
+    let raw: String = parse_flag_value(args, i)?;
+    raw.split(',')
+        .map(|v| {
+            v.trim()
+                .parse()
+                .map_err(|_| anyhow::anyhow!("invalid value for {}: {}", args[i], v))
+        })
+        .collect()
+}
+
+/// Parses an `RRGGBB` hex color, with or without a leading `#`.
+fn parse_hex_color(raw: &str) -> Result<(u8, u8, u8)> {
+    let hex = raw.trim().trim_start_matches('#');
+    let channel = |i: usize| hex.get(i..i + 2).and_then(|c| u8::from_str_radix(c, 16).ok());
+    match (hex.len(), channel(0), channel(2), channel(4)) {
+        (6, Some(r), Some(g), Some(b)) => Ok((r, g, b)),
+        _ => anyhow::bail!("invalid color: {}", raw),
+    }
+}
+
+#[cfg(windows)]
+fn get_preview_client_size(hwnd: isize) -> Option<(u32, u32)> {
+    let mut rect = RECT { left: 0, top: 0, right: 0, bottom: 0 };
+    let ok = unsafe { GetClientRect(hwnd as HWND, &mut rect as *mut RECT) };
+    if ok != 0 {
+        let width_i32 = rect.right.saturating_sub(rect.left);
+        let height_i32 = rect.bottom.saturating_sub(rect.top);
+        let w = u32::try_from(width_i32).ok()?;
+        let h = u32::try_from(height_i32).ok()?;
+        Some((w, h))
+    } else {
+        None
+    }
+}
+
+#[cfg(windows)]
+fn log_windows(msg: &str) {
+    use std::fs::OpenOptions;
+    let mut path = std::env::temp_dir();
+    path.push("rust_color_visuals_scr.log");
+    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+        let _ = writeln!(file, "{}", msg);
+    }
+}
+
+#[cfg(windows)]
+fn message_box_info(title: &str, body: &str) {
+    fn to_wide(s: &str) -> Vec<u16> {
+        use std::os::windows::ffi::OsStrExt;
+        std::ffi::OsStr::new(s).encode_wide().chain(std::iter::once(0)).collect()
+    }
+    let t = to_wide(title);
+    let b = to_wide(body);
+    unsafe {
+        MessageBoxW(0, b.as_ptr(), t.as_ptr(), MB_OK | MB_ICONINFORMATION);
+    }
+}
+
+/// Where frames are drawn: a window-backed `Pixels` surface, a buffer
+/// rendered at a multiple of the window size and box-filtered down to it, or
+/// a plain RGBA buffer when running headless.
+enum Canvas {
+    Window(Box<Pixels>),
+    Supersampled {
+        pixels: Box<Pixels>,
+        buffer: Vec<u8>,
+        factor: u32,
+    },
+    /// Draws at a fixed size whatever the window, shrunk to fit it (keeping
+    /// the aspect ratio) when presented.
+    Fixed {
+        pixels: Box<Pixels>,
+        buffer: Vec<u8>,
+        width: u32,
+        height: u32,
+    },
+    Offscreen(Vec<u8>),
+}
+
+impl Canvas {
+    fn offscreen(width: u32, height: u32) -> Self {
+        Canvas::Offscreen(vec![0; (width as usize) * (height as usize) * 4])
+    }
+
+    /// Wraps `pixels`, rendering at `factor` times its buffer size when
+    /// `factor` is above 1.
+    fn window(pixels: Pixels, factor: u32) -> Self {
+        if factor <= 1 {
+            return Canvas::Window(Box::new(pixels));
+        }
+        let len = pixels.frame().len() * (factor * factor) as usize;
+        Canvas::Supersampled {
+            pixels: Box::new(pixels),
+            buffer: vec![0; len],
+            factor,
+        }
+    }
+
+    /// Wraps `pixels`, drawing into a `width` x `height` buffer presented in
+    /// a `window_w` x `window_h` window.
+    fn fixed(pixels: Pixels, width: u32, height: u32, window_w: u32, window_h: u32) -> Result<Self> {
+        let mut canvas = Canvas::Fixed {
+            pixels: Box::new(pixels),
+            buffer: vec![0; (width as usize) * (height as usize) * 4],
+            width,
+            height,
+        };
+        canvas.fit_to_window(window_w, window_h)?;
+        Ok(canvas)
+    }
+
+    /// Buffer pixels per window pixel along each axis, rounded for a fixed
+    /// canvas.
+    fn scale(&self) -> u32 {
+        match self {
+            Canvas::Supersampled { factor, .. } => *factor,
+            Canvas::Fixed { pixels, width, .. } => {
+                let shown = pixels.context().texture_extent.width;
+                (*width as f32 / shown.max(1) as f32).round().max(1.0) as u32
+            }
+            _ => 1,
+        }
+    }
+
+    /// Sizes a fixed canvas's presentation to the largest that fits a
+    /// `width` x `height` window without cropping or enlarging the buffer.
+    fn fit_to_window(&mut self, window_w: u32, window_h: u32) -> Result<()> {
+        if let Canvas::Fixed { pixels, width, height, .. } = self {
+            let fit = (window_w as f32 / *width as f32).min(window_h as f32 / *height as f32).min(1.0);
+            let shown_w = ((*width as f32 * fit).round() as u32).max(1);
+            let shown_h = ((*height as f32 * fit).round() as u32).max(1);
+            pixels.resize_buffer(shown_w, shown_h)?;
+        }
+        Ok(())
+    }
+
+    fn frame(&self) -> &[u8] {
+        match self {
+            Canvas::Window(pixels) => pixels.frame(),
+            Canvas::Supersampled { buffer, .. } | Canvas::Fixed { buffer, .. } => buffer,
+            Canvas::Offscreen(buf) => buf,
+        }
+    }
+
+    fn frame_mut(&mut self) -> &mut [u8] {
+        match self {
+            Canvas::Window(pixels) => pixels.frame_mut(),
+            Canvas::Supersampled { buffer, .. } | Canvas::Fixed { buffer, .. } => buffer,
+            Canvas::Offscreen(buf) => buf,
+        }
+    }
+
+    /// Resizes the drawing buffer to `width` x `height`, which for a
+    /// supersampled canvas is `scale()` times the window size.
+    fn resize_buffer(&mut self, width: u32, height: u32) -> Result<()> {
+        match self {
+            Canvas::Window(pixels) => pixels.resize_buffer(width, height)?,
+            Canvas::Supersampled { pixels, buffer, factor } => {
+                pixels.resize_buffer(width / *factor, height / *factor)?;
+                buffer.resize((width as usize) * (height as usize) * 4, 0);
+            }
+            // Only ever its own size; see `fit_to_window`
+            Canvas::Fixed { .. } => {}
+            Canvas::Offscreen(buf) => buf.resize((width as usize) * (height as usize) * 4, 0),
+        }
+        Ok(())
+    }
+
+    fn resize_surface(&mut self, width: u32, height: u32) -> Result<()> {
+        if let Canvas::Window(pixels) | Canvas::Supersampled { pixels, .. } | Canvas::Fixed { pixels, .. } = self {
+            pixels.resize_surface(width, height)?;
+        }
+        Ok(())
+    }
+
+    /// Maps a physical window position to buffer coordinates.
+    fn window_to_buffer(&self, x: f32, y: f32) -> Vec2 {
+        match self {
+            Canvas::Window(pixels) | Canvas::Supersampled { pixels, .. } => {
+                let (px, py) = pixels
+                    .window_pos_to_pixel((x, y))
+                    .unwrap_or_else(|pos| pixels.clamp_pixel_pos(pos));
+                Vec2::new(px as f32, py as f32) * self.scale() as f32
+            }
+            Canvas::Fixed { pixels, width, .. } => {
+                let (px, py) = pixels
+                    .window_pos_to_pixel((x, y))
+                    .unwrap_or_else(|pos| pixels.clamp_pixel_pos(pos));
+                let shown = pixels.context().texture_extent.width;
+                Vec2::new(px as f32, py as f32) * (*width as f32 / shown as f32)
+            }
+            Canvas::Offscreen(_) => Vec2::new(x, y),
+        }
+    }
+
+    fn pixels(&self) -> Option<&Pixels> {
+        match self {
+            Canvas::Window(pixels) | Canvas::Supersampled { pixels, .. } | Canvas::Fixed { pixels, .. } => Some(pixels),
+            Canvas::Offscreen(_) => None,
+        }
+    }
+
+    /// Presents the frame, with `gui` drawn over it when given.
+    fn render(&mut self, gui: Option<&mut Gui>) -> Result<()> {
+        let pixels = match self {
+            Canvas::Window(pixels) => pixels,
+            Canvas::Supersampled { pixels, buffer, factor } => {
+                let size = pixels.context().texture_extent;
+                downsample_box(buffer, pixels.frame_mut(), size.width, size.height, *factor);
+                pixels
+            }
+            Canvas::Fixed { pixels, buffer, width, height } => {
+                let size = pixels.context().texture_extent;
+                downsample_area(buffer, *width, *height, pixels.frame_mut(), size.width, size.height);
+                pixels
+            }
+            Canvas::Offscreen(_) => return Ok(()),
+        };
+        match gui {
+            Some(gui) => pixels.render_with(|encoder, target, context| {
+                context.scaling_renderer.render(encoder, target);
+                gui.render(encoder, target, context);
+                Ok(())
+            })?,
+            None => pixels.render()?,
+        }
+        Ok(())
+    }
+}
+
+/// Averages each `factor` x `factor` block of `src` into one pixel of `dst`,
+/// which is `dst_w` x `dst_h`.
+fn downsample_box(src: &[u8], dst: &mut [u8], dst_w: u32, dst_h: u32, factor: u32) {
+    let src_w = (dst_w * factor) as usize;
+    let n = factor * factor;
+    for y in 0..dst_h as usize {
+        for x in 0..dst_w as usize {
+            let mut sum = [0u32; 3];
+            for sy in 0..factor as usize {
+                let row = (y * factor as usize + sy) * src_w;
+                for sx in 0..factor as usize {
+                    let i = (row + x * factor as usize + sx) * 4;
+                    sum[0] += src[i] as u32;
+                    sum[1] += src[i + 1] as u32;
+                    sum[2] += src[i + 2] as u32;
+                }
+            }
+            let o = (y * dst_w as usize + x) * 4;
+            dst[o] = (sum[0] / n) as u8;
+            dst[o + 1] = (sum[1] / n) as u8;
+            dst[o + 2] = (sum[2] / n) as u8;
+            dst[o + 3] = 255;
+        }
+    }
+}
+
+/// Shrinks `src` (`src_w` x `src_h`) into `dst` (`dst_w` x `dst_h`, no
+/// larger) by averaging the block of source pixels under each destination
+/// pixel, for ratios that needn't be whole.
+fn downsample_area(src: &[u8], src_w: u32, src_h: u32, dst: &mut [u8], dst_w: u32, dst_h: u32) {
+    let span = |i: usize, src_len: u32, dst_len: u32| {
+        let start = i * src_len as usize / dst_len as usize;
+        let end = ((i + 1) * src_len as usize / dst_len as usize).max(start + 1);
+        start..end
+    };
+    for y in 0..dst_h as usize {
+        let rows = span(y, src_h, dst_h);
+        for x in 0..dst_w as usize {
+            let cols = span(x, src_w, dst_w);
+            let mut sum = [0u32; 3];
+            for sy in rows.clone() {
+                for sx in cols.clone() {
+                    let i = (sy * src_w as usize + sx) * 4;
+                    sum[0] += src[i] as u32;
+                    sum[1] += src[i + 1] as u32;
+                    sum[2] += src[i + 2] as u32;
+                }
+            }
+            let n = (rows.len() * cols.len()) as u32;
+            let o = (y * dst_w as usize + x) * 4;
+            dst[o] = (sum[0] / n) as u8;
+            dst[o + 1] = (sum[1] / n) as u8;
+            dst[o + 2] = (sum[2] / n) as u8;
+            dst[o + 3] = 255;
+        }
+    }
+}
+
+/// Where and how densely `V` recordings are captured.
+#[derive(Clone, Debug)]
+struct GifSettings {
+    path: String,
+    /// Capture one frame out of every `frame_skip` rendered frames.
+    frame_skip: u64,
+    /// Recording stops and is written out once this many frames are held.
+    max_frames: usize,
+}
+
+impl Default for GifSettings {
+    fn default() -> Self {
+        Self {
+            path: "recording.gif".to_string(),
+            frame_skip: 2,
+            max_frames: 120,
+        }
+    }
+}
+
+/// Frames captured so far by an in-progress GIF recording.
+struct GifCapture {
+    frames: Vec<Frame>,
+    last_capture: Instant,
+}
+
+/// Frame rate and workload figures for the most recent frames.
+#[derive(Clone, Copy, Debug)]
+struct RenderStats {
+    fps: f32,
+    particles: usize,
+    segments: usize,
+}
+
+/// Rolling window of recent frame durations, in seconds.
+struct FrameTimes {
+    samples: [f32; 32],
+    len: usize,
+    next: usize,
+}
+
+impl FrameTimes {
+    fn new() -> Self {
+        Self {
+            samples: [0.0; 32],
+            len: 0,
+            next: 0,
+        }
+    }
+
+    fn push(&mut self, dt: f32) {
+        self.samples[self.next] = dt;
+        self.next = (self.next + 1) % self.samples.len();
+        self.len = (self.len + 1).min(self.samples.len());
+    }
+
+    fn fps(&self) -> f32 {
+        let total: f32 = self.samples[..self.len].iter().sum();
+        if total > 0.0 {
+            self.len as f32 / total
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Polls the `--config` file's modification time so edits can be applied
+/// while running.
+struct ConfigWatch {
+    path: String,
+    modified: Option<SystemTime>,
+    last_check: Instant,
+}
+
+impl ConfigWatch {
+    const INTERVAL: Duration = Duration::from_millis(500);
+
+    fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            modified: modified_time(path),
+            last_check: Instant::now(),
+        }
+    }
+
+    /// True at most once per change, checking no more often than `INTERVAL`.
+    fn changed(&mut self) -> bool {
+        if self.last_check.elapsed() < Self::INTERVAL {
+            return false;
+        }
+        self.last_check = Instant::now();
+        let modified = modified_time(&self.path);
+        if modified.is_some() && modified != self.modified {
+            self.modified = modified;
+            return true;
+        }
+        false
+    }
+}
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Color modes by the names `color` accepts, case-insensitively.
+const COLOR_MODES: [ColorMode; 7] = [
+    ColorMode::Direction,
+    ColorMode::Age,
+    ColorMode::Curl,
+    ColorMode::Speed,
+    ColorMode::Palette,
+    ColorMode::Duotone,
+    ColorMode::Divergence,
+];
+
+/// One line of the stdin command interface.
+#[derive(Debug)]
+enum StdinCommand {
+    /// A params field and its new value, written as in a TOML config.
+    Set(String, String),
+    Color(ColorMode),
+    Save,
+    Reseed,
+    Reset,
+    Pause,
+    Freeze,
+    Undo,
+}
+
+impl StdinCommand {
+    fn parse(line: &str) -> Result<StdinCommand> {
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let command = match name {
+            "set" => {
+                let key = words.next();
+                let value = words.collect::<Vec<_>>().join(" ");
+                match key {
+                    Some(key) if !value.is_empty() => return Ok(StdinCommand::Set(key.to_string(), value)),
+                    _ => bail!("usage: set KEY VALUE"),
+                }
+            }
+            "color" => {
+                let mode = words.next().unwrap_or_default();
+                match COLOR_MODES.iter().find(|m| format!("{:?}", m).eq_ignore_ascii_case(mode)) {
+                    Some(&mode) => StdinCommand::Color(mode),
+                    None => bail!("unknown color mode {:?}", mode),
+                }
+            }
+            "save" => StdinCommand::Save,
+            "reseed" => StdinCommand::Reseed,
+            "reset" => StdinCommand::Reset,
+            "pause" => StdinCommand::Pause,
+            "freeze" => StdinCommand::Freeze,
+            "undo" => StdinCommand::Undo,
+            _ => bail!("unknown command {:?}", name),
+        };
+        if let Some(extra) = words.next() {
+            bail!("unexpected {:?} after {}", extra, name);
+        }
+        Ok(command)
+    }
+}
+
+/// Reads commands from stdin on a background thread, one per line, and
+/// hands the ones that parse to the event loop. Lines that don't are
+/// reported there and then; blank lines are skipped.
+fn spawn_stdin_commands() -> mpsc::Receiver<StdinCommand> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if line.trim().is_empty() {
+                continue;
+            }
+            match StdinCommand::parse(&line) {
+                Ok(command) => {
+                    if tx.send(command).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => eprintln!("{:#}", e),
+            }
+        }
+    });
+    rx
+}
+
+/// An `ffmpeg` child process encoding raw RGBA frames piped to its stdin.
+struct Mp4Recorder {
+    path: String,
+    width: u32,
+    height: u32,
+    child: Child,
+    frames: u64,
+}
+
+impl Mp4Recorder {
+    fn start(path: &str, width: u32, height: u32, fps: u32) -> Result<Self> {
+        let child = Command::new("ffmpeg")
+            .args(["-y", "-loglevel", "error", "-f", "rawvideo", "-pix_fmt", "rgba"])
+            .args(["-s", &format!("{}x{}", width, height)])
+            .args(["-r", &fps.to_string(), "-i", "-"])
+            .args(["-c:v", "libx264", "-pix_fmt", "yuv420p", path])
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => anyhow::anyhow!("ffmpeg not found on PATH"),
+                _ => anyhow::anyhow!("starting ffmpeg: {}", e),
+            })?;
+        Ok(Self {
+            path: path.to_string(),
+            width,
+            height,
+            child,
+            frames: 0,
+        })
+    }
+
+    fn write_frame(&mut self, frame: &[u8]) -> Result<()> {
+        let stdin = self.child.stdin.as_mut().context("ffmpeg stdin closed")?;
+        stdin.write_all(frame)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Closes the pipe and waits for ffmpeg to finish writing the file.
+    fn finish(mut self) -> Result<()> {
+        drop(self.child.stdin.take());
+        let status = self.child.wait()?;
+        anyhow::ensure!(status.success(), "ffmpeg exited with {}", status);
+        Ok(())
+    }
+}
+
+/// CSV of per-frame timings for comparing performance changes, one row per
+/// rendered frame.
+struct FrameProfile {
+    path: String,
+    out: BufWriter<File>,
+    rows: u64,
+}
+
+impl FrameProfile {
+    /// Rows between flushes, so a crash loses little without writing every frame.
+    const FLUSH_EVERY: u64 = 60;
+
+    /// Opens `path` for appending, writing the header if the file is new.
+    fn open(path: &str) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("opening {}", path))?;
+        let empty = file.metadata()?.len() == 0;
+        let mut out = BufWriter::new(file);
+        if empty {
+            writeln!(out, "frame_index,frame_time_ms,particle_count,segments_drawn")?;
+        }
+        Ok(Self {
+            path: path.to_string(),
+            out,
+            rows: 0,
+        })
+    }
+
+    fn record(&mut self, frame: u64, work: Duration, particles: usize, segments: usize) -> Result<()> {
+        let ms = work.as_secs_f64() * 1000.0;
+        writeln!(self.out, "{},{:.3},{},{}", frame, ms, particles, segments)?;
+        self.rows += 1;
+        if self.rows.is_multiple_of(Self::FLUSH_EVERY) {
+            self.out.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Per-run folder for saved frames plus a `manifest.json` describing how
+/// each frame was made. The folder is only created on the first save.
+struct RunOutput {
+    base: PathBuf,
+    dir: Option<PathBuf>,
+    frames: Vec<serde_json::Value>,
+}
+
+impl RunOutput {
+    fn new(base: &str) -> Self {
+        Self {
+            base: PathBuf::from(base),
+            dir: None,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Starts a new run folder and manifest on the next save.
+    fn restart(&mut self) {
+        self.dir = None;
+        self.frames.clear();
+    }
+
+    fn dir(&mut self) -> Result<&Path> {
+        if self.dir.is_none() {
+            let dir = self.base.join(run_timestamp());
+            std::fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+            self.dir = Some(dir);
+        }
+        Ok(self.dir.as_deref().unwrap_or(Path::new(".")))
+    }
+}
+
+/// UTC `YYYYMMDD-HHMMSS` for naming run folders.
+fn run_timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    // Civil-from-days, valid for any date after 1970
+    let z = days as i64 + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
+
+/// `--seed` if given, otherwise the build-time seeds; printed so a run can be
+/// reproduced.
+fn seeds_from_cli(opts: &CliOptions) -> Seeds {
+    let seeds = opts.seed.map(Seeds::from_u64).unwrap_or_default();
+    println!("Noise seed: {}, RNG seed: {}", seeds.noise, seeds.rng);
+    seeds
+}
+
+/// `params` with pausing and the overlays as in `view`: undo leaves those be,
+/// as they change how the run is shown rather than the picture.
+fn with_view_of(mut params: Params, view: &Params) -> Params {
+    params.paused = view.paused;
+    params.show_field = view.show_field;
+    params.show_density = view.show_density;
+    params.show_hud = view.show_hud;
+    params
+}
+
+const PARAMS_SAVE_PATH: &str = "params.toml";
+const STATE_SAVE_PATH: &str = "state.bin";
+/// Key-driven param changes `U` can step back through.
+const PARAM_HISTORY_CAP: usize = 32;
+/// Radians Shift+E / Ctrl+E turn the field by: 15 degrees.
+const FIELD_ROTATION_STEP: f32 = std::f32::consts::PI / 12.0;
+/// Factor each zoom key press changes the field zoom by.
+const ZOOM_STEP: f32 = 1.25;
+/// Longer side of the `--save-field` thumbnails.
+const FIELD_THUMBNAIL_SIZE: u32 = 256;
+
+struct App {
+    sim: Simulator,
+    canvas: Canvas,
+    frame_index: u64,
+    palette_index: usize,
+    /// Gradient extracted by `--palette-from`, cycled after the built-ins.
+    image_palette: Option<Vec<palette::Stop>>,
+    overlay_backup: Vec<u8>,
+    gif_settings: GifSettings,
+    /// Save a PNG whenever `frame_index` reaches a multiple of this.
+    autosave_every: Option<u64>,
+    /// Save the field each PNG was drawn in next to it.
+    save_field: bool,
+    gif_capture: Option<GifCapture>,
+    mp4: Option<Mp4Recorder>,
+    profile: Option<FrameProfile>,
+    svg_max_segments: usize,
+    config_watch: Option<ConfigWatch>,
+    /// Commands read from stdin, when the window is taking them.
+    commands: Option<mpsc::Receiver<StdinCommand>>,
+    keymap: Keymap,
+    /// The `--gui` panel.
+    gui: Option<Gui>,
+    // Cursor in buffer coordinates and held buttons
+    cursor: Option<Vec2>,
+    spawn_held: bool,
+    attract_held: bool,
+    modifiers: ModifiersState,
+    frame_times: FrameTimes,
+    last_frame: Instant,
+    output: RunOutput,
+    rng_seed: u64,
+    /// Advance one step on the next update even while paused.
+    step_once: bool,
+    /// Frame time used instead of the wall clock, for reproducible runs.
+    fixed_dt: Option<f32>,
+    /// Fractional particles owed by `spawn_rate`, carried between frames.
+    spawn_carry: f32,
+    /// Params from before the field was frozen, to resume from; spawning is
+    /// off while set.
+    frozen: Option<Params>,
+    /// A single burst is out and per-frame spawning is off, until a reset.
+    burst_shot: bool,
+    /// Start a new burst at the next frame.
+    burst_pending: bool,
+    /// Params from before each recent key-driven change, newest last.
+    param_history: Vec<Params>,
+    /// Params the app started with, restored by Shift+Backspace.
+    startup_params: Params,
+}
+
+impl App {
+    /// Resizes to a `width` x `height` window. The simulation only adopts the
+    /// new size once the buffer has it, so saved frames always match what
+    /// was rendered.
+    fn resize(&mut self, width: u32, height: u32) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        if let Canvas::Fixed { .. } = self.canvas {
+            // The simulation keeps its size; only the presentation refits
+            if let Err(e) = self.canvas.fit_to_window(width, height) {
+                eprintln!("pixels buffer resize failed: {}", e);
+            }
+            return;
+        }
+        let scale = self.canvas.scale();
+        let (width, height) = (width * scale, height * scale);
+        if let Err(e) = self.canvas.resize_buffer(width, height) {
+            eprintln!("pixels buffer resize failed: {}", e);
+            return;
+        }
+        self.sim.resize(width, height);
+        // Clear the newly sized frame to the opaque background so no stale data shows
+        self.sim.clear(self.canvas.frame_mut());
+    }
+    fn new(mut canvas: Canvas, width: u32, height: u32, params: Params, seeds: Seeds) -> Self {
+        let startup_params = params.clone();
+        let sim = Simulator::new(width, height, params, seeds);
+        // Initialize frame to the background with opaque alpha
+        sim.clear(canvas.frame_mut());
+
+        Self {
+            sim,
+            canvas,
+            frame_index: 0,
+            palette_index: 0,
+            image_palette: None,
+            overlay_backup: Vec::new(),
+            gif_settings: GifSettings::default(),
+            autosave_every: None,
+            save_field: false,
+            gif_capture: None,
+            mp4: None,
+            profile: None,
+            svg_max_segments: 200_000,
+            config_watch: None,
+            commands: None,
+            keymap: Keymap::default(),
+            gui: None,
+            cursor: None,
+            spawn_held: false,
+            attract_held: false,
+            modifiers: ModifiersState::empty(),
+            frame_times: FrameTimes::new(),
+            last_frame: Instant::now(),
+            output: RunOutput::new("out"),
+            rng_seed: seeds.rng,
+            step_once: false,
+            fixed_dt: None,
+            spawn_carry: 0.0,
+            frozen: None,
+            burst_shot: false,
+            burst_pending: false,
+            param_history: Vec::new(),
+            startup_params,
+        }
+    }
+}
+
+impl App {
+    fn handle_key(&mut self, input: KeyboardInput) {
+        if input.state != ElementState::Pressed {
+            return;
+        }
+        let Some(action) = input.virtual_keycode.and_then(|key| self.keymap.action(key, self.modifiers)) else {
+            return;
+        };
+        if action == Action::Undo {
+            self.undo_params();
+            return;
+        }
+        // These bring back or hold params of their own, undone by loading again or releasing
+        if matches!(action, Action::LoadState | Action::ToggleFreeze) {
+            self.apply_action(action);
+            return;
+        }
+        let before = self.sim.params.clone();
+        self.apply_action(action);
+        self.remember_params(before);
+    }
+
+    /// Runs the panel for this frame and whatever its buttons asked for.
+    fn prepare_gui(&mut self, window: &Window) {
+        let Some(gui) = self.gui.as_mut() else {
+            return;
+        };
+        let max_spawn = (self.sim.width() * self.sim.height() / 4) as usize;
+        let actions = gui.prepare(window, &mut self.sim.params, max_spawn);
+        for action in actions {
+            let before = self.sim.params.clone();
+            self.apply_action(action);
+            self.remember_params(before);
+        }
+    }
+
+    /// Pushes `before` onto the undo history if the params have moved on
+    /// from it in more than pausing or the overlays.
+    fn remember_params(&mut self, before: Params) {
+        if with_view_of(before.clone(), &self.sim.params) != self.sim.params {
+            if self.param_history.len() == PARAM_HISTORY_CAP {
+                self.param_history.remove(0);
+            }
+            self.param_history.push(before);
+        }
+    }
+
+    /// Runs every command that has come in on stdin since the last call.
+    fn run_pending_commands(&mut self) {
+        let Some(commands) = self.commands.as_ref() else {
+            return;
+        };
+        let pending: Vec<StdinCommand> = commands.try_iter().collect();
+        for command in pending {
+            let before = self.sim.params.clone();
+            if let Err(e) = self.run_command(command) {
+                eprintln!("{:#}", e);
+            }
+            self.remember_params(before);
+        }
+    }
+
+    /// Does what the matching key does, or for `set`, overlays one field as
+    /// a config reload would.
+    fn run_command(&mut self, command: StdinCommand) -> Result<()> {
+        match command {
+            StdinCommand::Set(key, value) => {
+                // JSON rather than TOML, which would leave out unset optional params
+                let serde_json::Value::Object(table) = serde_json::to_value(&self.sim.params)? else {
+                    bail!("params did not serialize to an object");
+                };
+                if !table.contains_key(&key) {
+                    bail!("unknown param {:?}", key);
+                }
+                let params = self
+                    .sim
+                    .params
+                    .merged_with_toml(&format!("{} = {}", key, value))
+                    .with_context(|| format!("setting {}", key))?;
+                self.sim.set_params(params);
+            }
+            StdinCommand::Color(mode) => self.sim.params.color_mode = mode,
+            StdinCommand::Save => self.save_png()?,
+            StdinCommand::Reseed => self.reseed_noise(),
+            StdinCommand::Reset => self.reset(false, false),
+            StdinCommand::Pause => self.sim.params.paused = !self.sim.params.paused,
+            StdinCommand::Freeze => self.toggle_freeze(),
+            StdinCommand::Undo => self.undo_params(),
+        }
+        Ok(())
+    }
+
+    /// Restores the params from before the last key that changed them. The
+    /// live z slices are kept, so the field doesn't jump.
+    fn undo_params(&mut self) {
+        match self.param_history.pop() {
+            Some(params) => {
+                self.sim.set_params(with_view_of(params, &self.sim.params));
+                println!("Undo ({} left)", self.param_history.len());
+            }
+            None => println!("Nothing to undo"),
+        }
+    }
+
+    fn apply_action(&mut self, action: Action) {
+        match action {
+            Action::TogglePause => self.sim.params.paused = !self.sim.params.paused,
+            Action::SavePng => {
+                let _ = self.save_png();
+            }
+            Action::ToggleSvgRecording => self.toggle_svg_recording(),
+            Action::CopyToClipboard => match self.copy_to_clipboard() {
+                Ok(()) => println!("Copied frame to the clipboard"),
+                Err(e) => eprintln!("copying to the clipboard failed: {:#}", e),
+            },
+            Action::ReseedNoise => self.reseed_noise(),
+            Action::Reset => self.reset(false, false),
+            Action::ResetParams => self.reset(true, false),
+            Action::ResetReseed => self.reset(false, true),
+            Action::ResetParamsReseed => self.reset(true, true),
+            Action::ToggleGifRecording => self.toggle_gif_recording(),
+            Action::ToggleFreeze => self.toggle_freeze(),
+            Action::BurstShot => self.burst_pending = true,
+            Action::SaveParams => match self.save_params() {
+                Ok(()) => println!("Saved {}", PARAMS_SAVE_PATH),
+                Err(e) => eprintln!("saving params failed: {}", e),
+            },
+            Action::SaveState => match self.save_state(STATE_SAVE_PATH) {
+                Ok(()) => println!("Saved {}", STATE_SAVE_PATH),
+                Err(e) => eprintln!("saving state failed: {:#}", e),
+            },
+            Action::LoadState => match self.load_state(STATE_SAVE_PATH) {
+                Ok(()) => println!("Loaded {}", STATE_SAVE_PATH),
+                Err(e) => eprintln!("loading state failed: {:#}", e),
+            },
+            Action::Undo => self.undo_params(),
+            Action::ScaleXDown => self.scale_field(0.9, 1.0),
+            Action::ScaleXUp => self.scale_field(1.111, 1.0),
+            Action::ScaleYDown => self.scale_field(1.0, 0.9),
+            Action::ScaleYUp => self.scale_field(1.0, 1.111),
+            Action::ScaleDown => self.scale_field(0.9, 0.9),
+            Action::ScaleUp => self.scale_field(1.111, 1.111),
+            Action::ZStepDown => self.sim.params.z_step = (self.sim.params.z_step * 0.9).max(0.0001),
+            Action::ZStepUp => self.sim.params.z_step = (self.sim.params.z_step * 1.111).min(0.05),
+            Action::GammaDown => self.sim.params.gamma = (self.sim.params.gamma - 0.1).max(0.2),
+            Action::GammaUp => self.sim.params.gamma = (self.sim.params.gamma + 0.1).min(5.0),
+            Action::ForceDown => self.sim.params.force = (self.sim.params.force * 0.9).max(0.05),
+            Action::ForceUp => self.sim.params.force = (self.sim.params.force * 1.111).min(5.0),
+            Action::FrictionDown => {
+                self.sim.params.friction = (self.sim.params.friction - 0.002).max(0.90)
+            }
+            Action::FrictionUp => {
+                self.sim.params.friction = (self.sim.params.friction + 0.002).min(0.9995)
+            }
+            Action::CycleFadeMode => self.cycle_fade_mode(),
+            Action::FadeUp => self.sim.params.set_fade((self.sim.params.fade + 0.01).min(0.2)),
+            Action::FadeDown => self.sim.params.set_fade((self.sim.params.fade - 0.01).max(0.0)),
+            Action::CycleAgeEasing => self.cycle_age_easing(),
+            Action::CycleColorMode => self.cycle_color_mode(),
+            Action::CyclePalette => self.cycle_palette(),
+            Action::CycleNoiseKind => self.cycle_noise_kind(),
+            Action::RotateFieldBack => self.rotate_field(-FIELD_ROTATION_STEP),
+            Action::RotateFieldForward => self.rotate_field(FIELD_ROTATION_STEP),
+            Action::ToggleFieldMode => self.toggle_field_mode(),
+            Action::ToggleIntegrator => self.toggle_integrator(),
+            Action::CycleDomainWarp => self.cycle_domain_warp(),
+            Action::AddNoiseLayer => self.add_noise_layer(),
+            Action::RemoveNoiseLayer => self.remove_noise_layer(),
+            Action::CycleBoundaryMode => self.cycle_boundary_mode(),
+            Action::CycleSpawnBias => self.cycle_spawn_bias(),
+            Action::CycleSpawnMode => self.cycle_spawn_mode(),
+            Action::ToggleSmoothTrails => self.sim.params.smooth_trails = !self.sim.params.smooth_trails,
+            Action::ToggleAntialiasing => self.sim.params.antialiased = !self.sim.params.antialiased,
+            Action::CycleThickness => self.cycle_thickness(),
+            Action::CycleBlendMode => self.cycle_blend_mode(),
+            Action::CycleSplatMode => self.cycle_splat_mode(),
+            Action::StrokeAlphaDown => {
+                self.sim.params.stroke_alpha = (self.sim.params.stroke_alpha * 0.9).max(0.02)
+            }
+            Action::StrokeAlphaUp => {
+                self.sim.params.stroke_alpha = (self.sim.params.stroke_alpha * 1.111).min(1.0)
+            }
+            Action::ToggleDensity => self.sim.params.show_density = !self.sim.params.show_density,
+            Action::ToggleField => self.sim.params.show_field = !self.sim.params.show_field,
+            Action::ToggleLfo => self.sim.params.lfo = !self.sim.params.lfo,
+            Action::ToggleHud => self.sim.params.show_hud = !self.sim.params.show_hud,
+            Action::OctavesDown => self.sim.params.octaves = self.sim.params.octaves.saturating_sub(1).max(1),
+            Action::OctavesUp => self.sim.params.octaves = (self.sim.params.octaves + 1).min(8),
+            Action::ToggleRidged => self.sim.params.ridged = !self.sim.params.ridged,
+            Action::SaturationDown => self.sim.params.sat = (self.sim.params.sat - 0.1).max(0.0),
+            Action::SaturationUp => self.sim.params.sat = (self.sim.params.sat + 0.1).min(1.0),
+            Action::SymmetryDown => {
+                self.sim.params.symmetry = self.sim.params.symmetry.saturating_sub(1).max(1)
+            }
+            Action::SymmetryUp => self.sim.params.symmetry = (self.sim.params.symmetry + 1).min(12),
+            Action::HueRateDown => {
+                self.sim.params.hue_rate = (self.sim.params.hue_rate - 0.0005).max(-0.02)
+            }
+            Action::HueRateUp => self.sim.params.hue_rate = (self.sim.params.hue_rate + 0.0005).min(0.02),
+            Action::LifespanDown => {
+                self.sim.params.max_age = ((self.sim.params.max_age as f32) * 0.8).max(20.0) as u32
+            }
+            Action::LifespanUp => {
+                self.sim.params.max_age = ((self.sim.params.max_age as f32) * 1.25).min(100000.0) as u32
+            }
+            Action::WindLeft => self.nudge_wind(Vec2::new(-1.0, 0.0)),
+            Action::WindRight => self.nudge_wind(Vec2::new(1.0, 0.0)),
+            Action::WindUp => self.nudge_wind(Vec2::new(0.0, -1.0)),
+            Action::WindDown => self.nudge_wind(Vec2::new(0.0, 1.0)),
+            Action::PanLeft => self.pan_field(Vec2::new(-1.0, 0.0)),
+            Action::PanRight => self.pan_field(Vec2::new(1.0, 0.0)),
+            Action::PanUp => self.pan_field(Vec2::new(0.0, -1.0)),
+            Action::PanDown => self.pan_field(Vec2::new(0.0, 1.0)),
+            Action::ZoomIn => self.zoom_field(1.0 / ZOOM_STEP),
+            Action::ZoomOut => self.zoom_field(ZOOM_STEP),
+            Action::StepOnce => self.step_once = self.sim.params.paused,
+            Action::SpawnMore if self.sim.params.spawn_rate > 0.0 => self.sim.params.spawn_rate *= 1.1,
+            // Floored at 1/s so it doesn't drop back to per-frame spawning
+            Action::SpawnFewer if self.sim.params.spawn_rate > 0.0 => {
+                self.sim.params.spawn_rate = (self.sim.params.spawn_rate * 0.9).max(1.0)
+            }
+            Action::SpawnMore => {
+                let max = (self.sim.width() * self.sim.height() / 4) as usize;
+                self.sim.params.spawn_count = scale_count(self.sim.params.spawn_count, 1.1, 0, max)
+            }
+            Action::SpawnFewer => {
+                let max = (self.sim.width() * self.sim.height() / 4) as usize;
+                self.sim.params.spawn_count = scale_count(self.sim.params.spawn_count, 0.9, 0, max)
+            }
+            Action::StepsUp => {
+                self.sim.params.steps_per_frame = scale_count(self.sim.params.steps_per_frame, 1.1, 1, 2000)
+            }
+            Action::StepsDown => {
+                self.sim.params.steps_per_frame = scale_count(self.sim.params.steps_per_frame, 0.9, 1, 2000)
+            }
+            Action::ShadowSwatch(index) => self.pick_swatch(index, false),
+            Action::HighlightSwatch(index) => self.pick_swatch(index, true),
+        }
+    }
+
+    /// Multiplies the x and y field scales by the given factors, each kept
+    /// within 0.0005..=0.05.
+    fn scale_field(&mut self, x: f32, y: f32) {
+        let params = &mut self.sim.params;
+        params.scale_x = (params.scale_x * x).clamp(0.0005, 0.05);
+        params.scale_y = (params.scale_y * y).clamp(0.0005, 0.05);
+    }
+
+    /// Pushes the wind a small step along `dir`, each component capped at 2.
+    fn nudge_wind(&mut self, dir: Vec2) {
+        let wind = self.sim.params.wind + dir * 0.05;
+        self.sim.params.wind = wind.clamp(Vec2::splat(-2.0), Vec2::splat(2.0));
+    }
+
+    /// Slides the view of the field a tenth of the canvas's shorter side
+    /// along `dir`, as it appears on screen whatever the zoom.
+    fn pan_field(&mut self, dir: Vec2) {
+        let side = self.sim.width().min(self.sim.height()) as f32;
+        let p = &mut self.sim.params;
+        p.offset += dir * side * 0.1 * p.zoom;
+    }
+
+    /// Multiplies the field zoom by `factor`, keeping the field under the
+    /// middle of the canvas where it is.
+    fn zoom_field(&mut self, factor: f32) {
+        let center = Vec2::new(self.sim.width() as f32, self.sim.height() as f32) * 0.5;
+        let p = &mut self.sim.params;
+        let zoom = (p.zoom * factor).clamp(1.0 / 64.0, 64.0);
+        p.offset += center * (p.zoom - zoom);
+        p.zoom = zoom;
+    }
+
+    /// Turns the flow field by `delta` radians, kept in 0..TAU.
+    fn rotate_field(&mut self, delta: f32) {
+        let turned = (self.sim.params.field_rotation + delta).rem_euclid(std::f32::consts::TAU);
+        // Snap float drift so a full turn lands back on exactly 0
+        self.sim.params.field_rotation = if (turned - std::f32::consts::TAU).abs() < 1e-4 { 0.0 } else { turned };
+    }
+
+    fn handle_cursor_moved(&mut self, x: f32, y: f32) {
+        self.cursor = Some(self.canvas.window_to_buffer(x, y));
+    }
+
+    fn handle_mouse_button(&mut self, state: ElementState, button: MouseButton) {
+        let pressed = state == ElementState::Pressed;
+        match button {
+            MouseButton::Left => {
+                self.spawn_held = pressed;
+                if pressed {
+                    if let Some(pos) = self.cursor {
+                        self.sim.spawn_burst(pos, self.sim.params.mouse_burst);
+                    }
+                }
+            }
+            MouseButton::Right => self.attract_held = pressed,
+            _ => {}
+        }
+    }
+
+    fn cycle_color_mode(&mut self) {
+        self.sim.params.color_mode = match self.sim.params.color_mode {
+            ColorMode::Direction => ColorMode::Age,
+            ColorMode::Age => ColorMode::Curl,
+            ColorMode::Curl => ColorMode::Speed,
+            ColorMode::Speed => ColorMode::Palette,
+            ColorMode::Palette => ColorMode::Duotone,
+            ColorMode::Duotone => ColorMode::Divergence,
+            ColorMode::Divergence => ColorMode::Direction,
+        };
+    }
+
+    fn cycle_age_easing(&mut self) {
+        self.sim.params.age_easing = match self.sim.params.age_easing {
+            AgeEasing::Linear => AgeEasing::InOut,
+            AgeEasing::InOut => AgeEasing::Exponential,
+            AgeEasing::Exponential => AgeEasing::Linear,
+        };
+    }
+
+    fn cycle_spawn_mode(&mut self) {
+        self.sim.params.spawn_mode = match self.sim.params.spawn_mode {
+            SpawnMode::Line => SpawnMode::Uniform,
+            SpawnMode::Uniform => SpawnMode::Grid,
+            SpawnMode::Grid => SpawnMode::Circle,
+            SpawnMode::Circle => SpawnMode::Edges,
+            SpawnMode::Edges => SpawnMode::Line,
+        };
+        println!("Spawn mode: {:?}", self.sim.params.spawn_mode);
+    }
+
+    fn cycle_fade_mode(&mut self) {
+        self.sim.params.fade_mode = match self.sim.params.fade_mode {
+            FadeMode::Darken => FadeMode::Blur,
+            FadeMode::Blur => FadeMode::Persist,
+            FadeMode::Persist => FadeMode::Darken,
+        };
+        println!("Fade mode: {:?}", self.sim.params.fade_mode);
+    }
+
+    fn cycle_spawn_bias(&mut self) {
+        self.sim.params.spawn_bias = match self.sim.params.spawn_bias {
+            SpawnBias::None => SpawnBias::High,
+            SpawnBias::High => SpawnBias::Low,
+            SpawnBias::Low => SpawnBias::None,
+        };
+        println!("Spawn bias: {:?}", self.sim.params.spawn_bias);
+    }
+
+    fn cycle_boundary_mode(&mut self) {
+        self.sim.params.boundary = match self.sim.params.boundary {
+            BoundaryMode::Kill => BoundaryMode::Wrap,
+            BoundaryMode::Wrap => BoundaryMode::Bounce,
+            BoundaryMode::Bounce => BoundaryMode::Kill,
+        };
+    }
+
+    fn cycle_blend_mode(&mut self) {
+        self.sim.params.blend_mode = match self.sim.params.blend_mode {
+            BlendMode::Additive => BlendMode::AlphaOver,
+            BlendMode::AlphaOver => BlendMode::Max,
+            BlendMode::Max => BlendMode::Additive,
+        };
+        println!("Blend mode: {:?}", self.sim.params.blend_mode);
+    }
+
+    fn cycle_splat_mode(&mut self) {
+        self.sim.params.splat_mode = match self.sim.params.splat_mode {
+            SplatMode::Lines => SplatMode::Splats,
+            SplatMode::Splats => SplatMode::Both,
+            SplatMode::Both => SplatMode::Lines,
+        };
+        println!("Brush: {:?}", self.sim.params.splat_mode);
+    }
+
+    fn cycle_thickness(&mut self) {
+        const STEPS: [f32; 4] = [0.0, 0.5, 1.0, 2.0];
+        let current = STEPS.iter().position(|&t| t == self.sim.params.thickness);
+        let next = current.map_or(0, |i| (i + 1) % STEPS.len());
+        self.sim.params.thickness = STEPS[next];
+        println!("Line thickness: {}", STEPS[next]);
+    }
+
+    fn cycle_palette(&mut self) {
+        let count = palette::BUILTIN.len() + self.image_palette.is_some() as usize;
+        self.palette_index = (self.palette_index + 1) % count;
+        self.apply_palette();
+    }
+
+    fn apply_palette(&mut self) {
+        match (palette::BUILTIN.get(self.palette_index), &self.image_palette) {
+            (Some((name, stops)), _) => {
+                self.sim.palette = stops.to_vec();
+                println!("Palette: {}", name);
+            }
+            (None, Some(stops)) => {
+                self.sim.palette = stops.clone();
+                println!("Palette: image");
+            }
+            (None, None) => {}
+        }
+    }
+
+    /// Extracts a palette from `path` and makes it the active one.
+    fn use_image_palette(&mut self, path: &str) -> Result<()> {
+        self.image_palette = Some(palette::from_image(path, 8)?);
+        self.palette_index = palette::BUILTIN.len();
+        self.apply_palette();
+        self.sim.params.color_mode = ColorMode::Palette;
+        Ok(())
+    }
+
+    /// Sets the shadow (or with `high`, highlight) color to swatch `index`.
+    /// In `ColorMode::Palette` the active palette's end stop changes too.
+    fn pick_swatch(&mut self, index: usize, high: bool) {
+        let rgb = palette::SWATCHES[index];
+        let p = &mut self.sim.params;
+        let stop = if high {
+            p.color_hi = rgb;
+            self.sim.palette.last_mut()
+        } else {
+            p.color_lo = rgb;
+            self.sim.palette.first_mut()
+        };
+        if let (ColorMode::Palette, Some(stop)) = (p.color_mode, stop) {
+            stop.1 = rgb;
+        }
+    }
+
+    fn cycle_noise_kind(&mut self) {
+        let kind = match self.sim.params.noise_kind {
+            NoiseKind::Perlin => NoiseKind::OpenSimplex,
+            NoiseKind::OpenSimplex => NoiseKind::Worley,
+            NoiseKind::Worley => NoiseKind::Perlin,
+        };
+        self.sim.set_noise_kind(kind);
+    }
+
+    fn toggle_field_mode(&mut self) {
+        self.sim.params.field_mode = match self.sim.params.field_mode {
+            FieldMode::Angle => FieldMode::Curl,
+            FieldMode::Curl => FieldMode::Angle,
+        };
+    }
+
+    fn toggle_integrator(&mut self) {
+        self.sim.params.integrator = match self.sim.params.integrator {
+            Integrator::Euler => Integrator::Midpoint,
+            Integrator::Midpoint => Integrator::Euler,
+        };
+        println!("Integrator: {:?}", self.sim.params.integrator);
+    }
+
+    fn cycle_domain_warp(&mut self) {
+        self.sim.params.domain_warp = match self.sim.params.domain_warp {
+            DomainWarp::None => DomainWarp::Polar,
+            DomainWarp::Polar => DomainWarp::Swirl,
+            DomainWarp::Swirl => DomainWarp::None,
+        };
+        println!("Domain warp: {:?}", self.sim.params.domain_warp);
+    }
+
+    /// Adds a finer, faster-moving layer than the last one, up to four extra.
+    fn add_noise_layer(&mut self) {
+        let params = &mut self.sim.params;
+        if params.layers.len() >= 4 {
+            return;
+        }
+        let (scale, z_step) = params
+            .layers
+            .last()
+            .map_or((params.scale(), params.z_step), |l| (l.scale, l.z_step));
+        params.layers.push(NoiseLayer {
+            scale: scale * 3.0,
+            z: 0.0,
+            z_step: z_step * 2.0,
+            weight: 0.3,
+        });
+        println!("Noise layers: {}", params.layers.len() + 1);
+    }
+
+    fn remove_noise_layer(&mut self) {
+        if self.sim.params.layers.pop().is_some() {
+            println!("Noise layers: {}", self.sim.params.layers.len() + 1);
+        }
+    }
+
+    /// Clears the canvas and restarts the simulation from an empty field.
+    /// Tuned params survive unless `restore_params`; `reseed` also draws a
+    /// new noise seed.
+    fn reset(&mut self, restore_params: bool, reseed: bool) {
+        if restore_params {
+            self.sim.set_params(self.startup_params.clone());
+            self.frozen = None;
+        }
+        self.sim.reset();
+        self.burst_shot = false;
+        self.burst_pending = false;
+        if reseed {
+            self.reseed_noise();
+        }
+        self.sim.clear(self.canvas.frame_mut());
+        self.frame_index = 0;
+        self.output.restart();
+        println!("Simulation reset");
+    }
+
+    /// Starts a single shot: a cleared canvas with one burst of
+    /// `burst_count` particles on it and no more spawning, so the picture
+    /// settles instead of accumulating forever.
+    fn start_burst(&mut self) {
+        self.sim.clear_particles();
+        self.sim.clear(self.canvas.frame_mut());
+        self.sim.spawn_n(self.sim.params.burst_count);
+        self.burst_shot = true;
+        println!("Burst of {} particles", self.sim.params.burst_count);
+    }
+
+    /// Holds the field still on a cleared canvas: z, the layers, the hue,
+    /// the LFOs and the fade stop and no more particles spawn, so the ones
+    /// alive trace complete streamlines of one snapshot. Toggling again
+    /// resumes the animation.
+    fn toggle_freeze(&mut self) {
+        let p = &mut self.sim.params;
+        match self.frozen.take() {
+            Some(saved) => {
+                p.z_step = saved.z_step;
+                p.hue_rate = saved.hue_rate;
+                p.fade = saved.fade;
+                p.fade_mode = saved.fade_mode;
+                p.lfo = saved.lfo;
+                for (layer, saved) in p.layers.iter_mut().zip(&saved.layers) {
+                    layer.z_step = saved.z_step;
+                }
+                println!("Field released");
+            }
+            None => {
+                self.frozen = Some(p.clone());
+                p.z_step = 0.0;
+                p.hue_rate = 0.0;
+                p.fade = 0.0;
+                p.fade_mode = FadeMode::Persist;
+                p.lfo = false;
+                for layer in &mut p.layers {
+                    layer.z_step = 0.0;
+                }
+                self.sim.clear(self.canvas.frame_mut());
+                println!("Field frozen");
+            }
+        }
+    }
+
+    fn reseed_noise(&mut self) {
+        let seed = self.sim.reseed_noise();
+        println!("Noise seed: {}", seed);
+    }
+
+    /// Copy of the current frame as it should be exported: alpha forced
+    /// opaque, HDR tone-mapped when on, and the vignette and gamma applied.
+    fn export_pixels(&self) -> Vec<u8> {
+        let mut data = self.canvas.frame().to_vec();
+        for i in (0..data.len()).step_by(4) {
+            data[i + 3] = 255;
+        }
+        self.sim.apply_hdr_tone_map(&mut data);
+        self.sim.apply_post_effects(&mut data, self.sim.width(), self.sim.height());
+        data
+    }
+
+    /// The hi-res accumulator box-filtered down to the canvas size, when
+    /// `--ss` is on.
+    fn supersampled_image(&self) -> Option<RgbaImage> {
+        let (factor, hires) = self.sim.supersampled()?;
+        let (width, height) = (self.sim.width(), self.sim.height());
+        let mut data = vec![0u8; (width * height * 4) as usize];
+        downsample_box(hires, &mut data, width, height, factor);
+        self.sim.apply_post_effects(&mut data, width, height);
+        ImageBuffer::from_raw(width, height, data)
+    }
+
+    /// Copies the current frame into an image with alpha forced opaque.
+    fn frame_image(&self) -> RgbaImage {
+        let data = self.export_pixels();
+        ImageBuffer::<Rgba<u8>, _>::from_raw(self.sim.width(), self.sim.height(), data).expect("buffer dims")
+    }
+
+    /// Saves the current frame into the run folder and appends its params
+    /// and seeds to the run's `manifest.json`.
+    fn save_png(&mut self) -> anyhow::Result<()> {
+        self.save_png_as(&format!("frame_{:06}.png", self.frame_index))
+    }
+
+    /// The image saves and copies export: the supersampled frame when `--ss`
+    /// is on, the canvas otherwise.
+    fn export_image(&self) -> RgbaImage {
+        self.supersampled_image().unwrap_or_else(|| self.frame_image())
+    }
+
+    /// Puts the exported frame on the system clipboard as an image.
+    fn copy_to_clipboard(&self) -> Result<()> {
+        let img = self.export_image();
+        let (width, height) = img.dimensions();
+        let mut clipboard = arboard::Clipboard::new()?;
+        clipboard.set_image(arboard::ImageData {
+            width: width as usize,
+            height: height as usize,
+            bytes: img.into_raw().into(),
+        })?;
+        Ok(())
+    }
+
+    fn save_png_as(&mut self, filename: &str) -> anyhow::Result<()> {
+        let img = self.export_image();
+        let dir = self.output.dir()?.to_path_buf();
+        let path = dir.join(filename);
+        rust_color_visuals::save_png(&path, img.width(), img.height(), img.as_raw())?;
+
+        let mut entry = serde_json::json!({
+            "file": filename,
+            "noise_seed": self.sim.noise_seed(),
+            "params": self.sim.params,
+        });
+        if self.sim.is_hdr() {
+            let hdr_name = format!("{}_hdr.png", filename.trim_end_matches(".png"));
+            self.sim.save_hdr(&dir.join(&hdr_name))?;
+            entry["hdr_file"] = hdr_name.into();
+        }
+        if self.save_field {
+            let field_name = format!("{}_field.png", filename.trim_end_matches(".png"));
+            self.sim.field_image(FIELD_THUMBNAIL_SIZE).save(dir.join(&field_name))?;
+            entry["field_file"] = field_name.into();
+        }
+        self.output.frames.push(entry);
+        let manifest = serde_json::json!({
+            "width": self.sim.width(),
+            "height": self.sim.height(),
+            "rng_seed": self.rng_seed,
+            "frames": self.output.frames,
+        });
+        std::fs::write(dir.join("manifest.json"), serde_json::to_string_pretty(&manifest)?)?;
+        println!("Saved {}", path.display());
+        Ok(())
+    }
+
+    /// Applies the command-line options shared by every mode: flow and
+    /// palette sources, GPU, resumed state, export settings and recorders.
+    fn apply_cli_options(&mut self, opts: &CliOptions) -> Result<()> {
+        self.sim.image_field = field_image_from_cli(opts)?;
+        if opts.gpu {
+            self.enable_gpu();
+        }
+        if let Some(path) = &opts.load_state {
+            self.load_state(path)?;
         }
-        if al.starts_with("/p:") || al.starts_with("-p:") {
-            let handle_str = al.split_once(':').map(|(_, r)| r.to_string()).unwrap_or_default();
-            if let Ok(h) = isize::from_str_radix(handle_str.trim(), 10) {
-                return ScreenSaverMode::Preview(h);
-            }
-            return ScreenSaverMode::Preview(0);
+        if let Some(path) = &opts.palette_from {
+            self.use_image_palette(path)?;
         }
-        if al == "/p" || al == "-p" {
-            let hwnd = args.get(i + 1).cloned().unwrap_or_else(|| "0".to_string());
-            if let Ok(h) = isize::from_str_radix(hwnd.trim(), 10) {
-                return ScreenSaverMode::Preview(h);
-            }
-            return ScreenSaverMode::Preview(0);
+        self.sim.target_fps = opts.fps.unwrap_or(60) as f32;
+        if let Some(path) = &opts.background_image {
+            let image = image::open(path).with_context(|| format!("opening {}", path))?;
+            self.sim.background = Some(image.into_rgb8());
+            self.sim.clear(self.canvas.frame_mut());
         }
-        i += 1;
+        if opts.ss > 1 {
+            self.sim.enable_supersampling(opts.ss);
+        }
+        if opts.hdr {
+            self.sim.enable_hdr();
+        }
+        self.save_field = opts.save_field;
+        // Before the recorders start, so none of them captures it
+        for _ in 0..opts.warmup {
+            self.step_once = true;
+            self.update(headless_dt(opts));
+        }
+        self.svg_max_segments = opts.svg_max_segments;
+        if opts.record_svg {
+            self.toggle_svg_recording();
+        }
+        if let Some(count) = opts.trace {
+            self.sim.start_tracing(count);
+        }
+        self.output = RunOutput::new(&opts.outdir);
+        if let Some(path) = &opts.record_mp4 {
+            self.start_mp4(path, opts.fps.unwrap_or(60));
+        }
+        self.profile = opts.profile.as_deref().map(FrameProfile::open).transpose()?;
+        Ok(())
     }
-    ScreenSaverMode::Normal
-}
 
-#[cfg(windows)]
-fn get_preview_client_size(hwnd: isize) -> Option<(u32, u32)> {
-    let mut rect = RECT { left: 0, top: 0, right: 0, bottom: 0 };
-    let ok = unsafe { GetClientRect(hwnd as HWND, &mut rect as *mut RECT) };
-    if ok != 0 {
-        let width_i32 = rect.right.saturating_sub(rect.left);
-        let height_i32 = rect.bottom.saturating_sub(rect.top);
-        let w = u32::try_from(width_i32).ok()?;
-        let h = u32::try_from(height_i32).ok()?;
-        Some((w, h))
-    } else {
-        None
+    fn save_params(&self) -> Result<()> {
+        let text = self.sim.params.to_toml()?;
+        std::fs::write(PARAMS_SAVE_PATH, text)?;
+        Ok(())
     }
-}
 
-#[cfg(windows)]
-fn log_windows(msg: &str) {
-    use std::fs::OpenOptions;
-    let mut path = std::env::temp_dir();
-    path.push("rust_color_visuals_scr.log");
-    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
-        use std::io::Write;
-        let _ = writeln!(file, "{}", msg);
+    fn save_state(&self, path: &str) -> Result<()> {
+        let checkpoint = Checkpoint {
+            sim: self.sim.state(),
+            frame_index: self.frame_index,
+            frame: self.canvas.frame().to_vec(),
+        };
+        Ok(checkpoint.save(path)?)
     }
-}
 
-#[cfg(windows)]
-fn message_box_info(title: &str, body: &str) {
-    fn to_wide(s: &str) -> Vec<u16> {
-        use std::os::windows::ffi::OsStrExt;
-        std::ffi::OsStr::new(s).encode_wide().chain(std::iter::once(0)).collect()
-    }
-    let t = to_wide(title);
-    let b = to_wide(body);
-    unsafe {
-        MessageBoxW(0, b.as_ptr(), t.as_ptr(), MB_OK | MB_ICONINFORMATION);
+    /// Restores a checkpoint written by `save_state`. The canvas must be the
+    /// size it was saved at.
+    fn load_state(&mut self, path: &str) -> Result<()> {
+        let checkpoint = Checkpoint::load(path)?;
+        checkpoint.check_size(self.sim.width(), self.sim.height())?;
+        self.canvas.frame_mut().copy_from_slice(&checkpoint.frame);
+        self.sim.restore(checkpoint.sim, &checkpoint.frame);
+        self.frame_index = checkpoint.frame_index;
+        Ok(())
     }
-}
-
-#[derive(Clone, Copy)]
-struct Particle {
-    pos: Vec2,
-    vel: Vec2,
-    age: u32,
-    alive: bool,
-}
 
-impl Particle {
-    fn new(pos: Vec2) -> Self {
-        Self {
-            pos,
-            vel: Vec2::ZERO,
-            age: 0,
-            alive: true,
-        }
-    }
-}
-
-#[derive(Clone, Copy)]
-enum ColorMode {
-    Direction,
-    Age,
-    Curl,
-}
-
-fn hsv_to_rgb(mut h: f32, s: f32, v: f32) -> (u8, u8, u8) {
-    let s = s.clamp(0.0, 1.0);
-    let v = v.clamp(0.0, 1.0);
-    h = h.fract();
-    if h < 0.0 {
-        h += 1.0;
-    }
-    let i = (h * 6.0).floor() as i32;
-    let f = h * 6.0 - i as f32;
-    let p = v * (1.0 - s);
-    let q = v * (1.0 - f * s);
-    let t = v * (1.0 - (1.0 - f) * s);
-    let (r, g, b) = match i.rem_euclid(6) {
-        0 => (v, t, p),
-        1 => (q, v, p),
-        2 => (p, v, t),
-        3 => (p, q, v),
-        4 => (t, p, v),
-        _ => (v, p, q),
-    };
-    (
-        (r * 255.0).clamp(0.0, 255.0) as u8,
-        (g * 255.0).clamp(0.0, 255.0) as u8,
-        (b * 255.0).clamp(0.0, 255.0) as u8,
-    )
-}
+    /// Re-reads the watched config file if it changed. Keys it sets override
+    /// the live params; a file that fails to parse is reported and ignored.
+    fn reload_config_if_changed(&mut self) {
+        let Some(watch) = self.config_watch.as_mut() else {
+            return;
+        };
+        if !watch.changed() {
+            return;
+        }
+        match Params::load(&watch.path, &self.sim.params) {
+            Ok(params) => {
+                self.sim.set_params(params);
+                println!("Reloaded {}", watch.path);
+            }
+            Err(e) => eprintln!("config reload failed, keeping current params: {:#}", e),
+        }
+    }
 
-struct Params {
-    scale: f32,
-    z: f32,
-    z_step: f32,
-    force: f32,
-    friction: f32,
-    steps_per_frame: usize,
-    spawn_count: usize,
-    fade: f32,
-    color_mode: ColorMode,
-    paused: bool,
-}
+    fn toggle_gif_recording(&mut self) {
+        if self.gif_capture.is_some() {
+            self.finish_gif_recording();
+        } else {
+            println!("Recording GIF to {}", self.gif_settings.path);
+            self.gif_capture = Some(GifCapture {
+                frames: Vec::new(),
+                last_capture: Instant::now(),
+            });
+        }
+    }
 
-struct App {
-    width: u32,
-    height: u32,
-    pixels: Pixels,
-    perlin: Perlin,
-    noise_seed: u32,
-    rng: StdRng,
-    params: Params,
-    particles: Vec<Particle>,
-    frame_index: u64,
-}
+    fn toggle_svg_recording(&mut self) {
+        if self.sim.is_recording() {
+            self.finish_svg_recording();
+        } else {
+            println!("Recording segments for SVG (up to {})", self.svg_max_segments);
+            self.sim.start_recording(self.svg_max_segments);
+        }
+    }
 
-impl App {
-    fn resize(&mut self, width: u32, height: u32) {
-        if width == 0 || height == 0 {
+    /// Writes the recorded segments to an SVG in the run folder.
+    fn finish_svg_recording(&mut self) {
+        let Some(segments) = self.sim.take_recording() else {
             return;
+        };
+        let filename = format!("streamlines_{:06}.svg", self.frame_index);
+        let (width, height) = (self.sim.width(), self.sim.height());
+        let result = self.output.dir().and_then(|dir| {
+            let path = dir.join(&filename);
+            rust_color_visuals::svg::write_svg(&path, width, height, &segments)?;
+            Ok(path)
+        });
+        match result {
+            Ok(path) => println!("Saved {} ({} segments)", path.display(), segments.len()),
+            Err(e) => eprintln!("SVG export failed: {:#}", e),
         }
-        self.width = width;
-        self.height = height;
-        if let Err(e) = self.pixels.resize_buffer(width, height) {
-            eprintln!("pixels buffer resize failed: {}", e);
+    }
+
+    fn capture_gif_frame(&mut self) {
+        if self.gif_capture.is_none() || !self.frame_index.is_multiple_of(self.gif_settings.frame_skip) {
             return;
         }
-        // Clear the newly sized frame to fully opaque black so no stale data shows
-        let frame = self.pixels.frame_mut();
-        for px in frame.chunks_exact_mut(4) {
-            px[0] = 0;
-            px[1] = 0;
-            px[2] = 0;
-            px[3] = 255;
-        }
-    }
-    fn new(mut pixels: Pixels, width: u32, height: u32) -> Self {
-        // Initialize frame to black and opaque alpha
-        {
-            let frame = pixels.frame_mut();
-            for px in frame.chunks_exact_mut(4) {
-                px[0] = 0;
-                px[1] = 0;
-                px[2] = 0;
-                px[3] = 255;
-            }
-        }
-
-        let width = width;
-        let height = height;
-        let noise_seed = build_config::NOISE_SEED;
-        let perlin = Perlin::new(noise_seed);
-        let rng = StdRng::seed_from_u64(build_config::RNG_SEED);
-
-        let color_mode = match build_config::COLOR_MODE_STR {
-            "Age" => ColorMode::Age,
-            "Curl" => ColorMode::Curl,
-            _ => ColorMode::Direction,
-        };
-
-        let spawn_count_from_factor = ((height as f32) * build_config::SPAWN_COUNT_FACTOR)
-            .round()
-            .max(1.0) as usize;
-
-        let params = Params {
-            scale: build_config::SCALE,
-            z: build_config::Z,
-            z_step: build_config::Z_STEP,
-            force: build_config::FORCE,
-            friction: build_config::FRICTION,
-            steps_per_frame: build_config::STEPS_PER_FRAME as usize,
-            spawn_count: spawn_count_from_factor,
-            fade: build_config::FADE,
-            color_mode,
-            paused: build_config::PAUSED,
+        let img = self.frame_image();
+        let Some(capture) = self.gif_capture.as_mut() else {
+            return;
         };
-
-        Self {
-            width,
-            height,
-            pixels,
-            perlin,
-            noise_seed,
-            rng,
-            params,
-            particles: Vec::with_capacity((width * height / 4) as usize),
-            frame_index: 0,
+        let now = Instant::now();
+        let delay = Delay::from_saturating_duration(now - capture.last_capture);
+        capture.last_capture = now;
+        capture.frames.push(Frame::from_parts(img, 0, 0, delay));
+        let captured = capture.frames.len();
+        // One whole cycle from wherever it started plays back seamlessly
+        let loop_frames = self.sim.params.loop_frames as u64;
+        if loop_frames > 0 && captured as u64 * self.gif_settings.frame_skip >= loop_frames {
+            println!("GIF covers one {} frame loop", loop_frames);
+            self.finish_gif_recording();
+        } else if captured >= self.gif_settings.max_frames {
+            println!("GIF frame cap of {} reached", self.gif_settings.max_frames);
+            self.finish_gif_recording();
         }
     }
-}
 
-impl App {
-    fn handle_key(&mut self, input: KeyboardInput) {
-        if input.state != ElementState::Pressed {
-            return;
+    /// Moves particle stepping to the GPU, staying on the CPU if it can't.
+    fn enable_gpu(&mut self) {
+        match self.sim.enable_gpu() {
+            Ok(()) => println!("Stepping particles on the GPU"),
+            Err(e) => eprintln!("GPU stepping unavailable, using the CPU: {:#}", e),
         }
-        if let Some(key) = input.virtual_keycode {
-            match key {
-                VirtualKeyCode::Space => {
-                    self.params.paused = !self.params.paused;
-                }
-                VirtualKeyCode::S => {
-                    let _ = self.save_png();
-                }
-                VirtualKeyCode::R => self.reseed_noise(),
-                VirtualKeyCode::LBracket => {
-                    self.params.scale = (self.params.scale * 0.9).max(0.0005)
-                }
-                VirtualKeyCode::RBracket => {
-                    self.params.scale = (self.params.scale * 1.111).min(0.05)
-                }
-                VirtualKeyCode::Comma => {
-                    self.params.z_step = (self.params.z_step * 0.9).max(0.0001)
-                }
-                VirtualKeyCode::Period => {
-                    self.params.z_step = (self.params.z_step * 1.111).min(0.05)
-                }
-                VirtualKeyCode::Slash => self.params.force = (self.params.force * 0.9).max(0.05),
-                VirtualKeyCode::Equals => self.params.force = (self.params.force * 1.111).min(5.0),
-                VirtualKeyCode::Key9 => {
-                    self.params.friction = (self.params.friction - 0.002).max(0.90)
-                }
-                VirtualKeyCode::Key0 => {
-                    self.params.friction = (self.params.friction + 0.002).min(0.9995)
-                }
-                VirtualKeyCode::F => self.params.fade = (self.params.fade + 0.01).min(0.2),
-                VirtualKeyCode::G => self.params.fade = (self.params.fade - 0.01).max(0.0),
-                VirtualKeyCode::C => self.cycle_color_mode(),
-                VirtualKeyCode::Up => {
-                    self.params.spawn_count = ((self.params.spawn_count as f32) * 1.1).min(10000000.0) as usize
-                }
-                VirtualKeyCode::Down => {
-                    self.params.spawn_count = ((self.params.spawn_count as f32) * 0.9).max(1.0) as usize
-                }
-                _ => {}
+    }
+
+    /// Starts piping frames to ffmpeg; failures (e.g. ffmpeg missing) are
+    /// reported and leave the app running without recording.
+    fn start_mp4(&mut self, path: &str, fps: u32) {
+        match Mp4Recorder::start(path, self.sim.width(), self.sim.height(), fps) {
+            Ok(recorder) => {
+                println!("Recording MP4 to {}", path);
+                self.mp4 = Some(recorder);
             }
+            Err(e) => eprintln!("MP4 recording disabled: {}", e),
         }
     }
 
-    fn cycle_color_mode(&mut self) {
-        self.params.color_mode = match self.params.color_mode {
-            ColorMode::Direction => ColorMode::Age,
-            ColorMode::Age => ColorMode::Curl,
-            ColorMode::Curl => ColorMode::Direction,
+    fn capture_mp4_frame(&mut self) {
+        if self.mp4.is_none() {
+            return;
+        }
+        let pixels = self.export_pixels();
+        let recorder = self.mp4.as_mut().expect("checked above");
+        // ffmpeg was told a fixed frame size at startup
+        let size_changed = (recorder.width, recorder.height) != (self.sim.width(), self.sim.height());
+        let result = if size_changed {
+            Err(anyhow::anyhow!("window was resized"))
+        } else {
+            recorder.write_frame(&pixels)
         };
+        if let Err(e) = result {
+            eprintln!("MP4 recording stopped: {}", e);
+            self.finish_mp4();
+        }
     }
 
-    fn reseed_noise(&mut self) {
-        let seed: u32 = self.rng.gen();
-        self.noise_seed = seed;
-        self.perlin = Perlin::new(seed);
+    fn finish_mp4(&mut self) {
+        let Some(recorder) = self.mp4.take() else {
+            return;
+        };
+        let (path, count) = (recorder.path.clone(), recorder.frames);
+        match recorder.finish() {
+            Ok(()) => println!("Saved {} ({} frames)", path, count),
+            Err(e) => eprintln!("MP4 encode failed: {}", e),
+        }
     }
 
-    fn save_png(&mut self) -> anyhow::Result<()> {
-        let frame = self.pixels.frame();
-        let mut data = frame.to_vec();
-        for i in (0..data.len()).step_by(4) {
-            data[i + 3] = 255;
+    /// Writes the traced paths to `traces.csv` and `traces.geojson` in the
+    /// run folder.
+    fn finish_trace(&mut self) {
+        let Some(trace) = self.sim.take_trace() else {
+            return;
+        };
+        let result = self.output.dir().and_then(|dir| {
+            let (csv, geojson) = (dir.join("traces.csv"), dir.join("traces.geojson"));
+            trace.write_csv(&csv)?;
+            trace.write_geojson(&geojson)?;
+            Ok((csv, geojson))
+        });
+        match result {
+            Ok((csv, geojson)) => println!(
+                "Saved {} and {} ({} paths)",
+                csv.display(),
+                geojson.display(),
+                trace.paths().len()
+            ),
+            Err(e) => eprintln!("trace export failed: {:#}", e),
         }
-        let img: ImageBuffer<Rgba<u8>, _> =
-            ImageBuffer::from_raw(self.width, self.height, data).expect("buffer dims");
-        let filename = format!("frame_{:06}.png", self.frame_index);
-        img.save(&filename)?;
-        println!("Saved {}", filename);
-        Ok(())
     }
 
-    fn apply_fade(&mut self) {
-        let fade_scale = 1.0 - self.params.fade;
-        if fade_scale >= 1.0 {
+    fn finish_profile(&mut self) {
+        let Some(mut profile) = self.profile.take() else {
             return;
-        }
-        let frame = self.pixels.frame_mut();
-        for px in frame.chunks_exact_mut(4) {
-            px[0] = ((px[0] as f32) * fade_scale) as u8;
-            px[1] = ((px[1] as f32) * fade_scale) as u8;
-            px[2] = ((px[2] as f32) * fade_scale) as u8;
-            px[3] = 255;
+        };
+        match profile.out.flush() {
+            Ok(()) => println!("Saved {} ({} frames)", profile.path, profile.rows),
+            Err(e) => eprintln!("writing {} failed: {}", profile.path, e),
         }
     }
 
-    fn spawn_particles(&mut self) {
-        let width_f = self.width as f32;
-        let height_f = self.height as f32;
-        let x = width_f * 0.5;
-        let count = self.params.spawn_count;
-        if count == 0 {
+    fn finish_gif_recording(&mut self) {
+        let Some(capture) = self.gif_capture.take() else {
             return;
+        };
+        let count = capture.frames.len();
+        match write_gif(&self.gif_settings.path, capture.frames) {
+            Ok(()) => println!("Saved {} ({} frames)", self.gif_settings.path, count),
+            Err(e) => eprintln!("GIF encode failed: {}", e),
         }
-        let mut spawned = 0usize;
-        let mut i = 0usize;
-        while spawned < count && i < self.particles.len() {
-            if !self.particles[i].alive {
-                let t = spawned as f32 / count as f32;
-                let base_y = t * height_f;
-                let jitter = self.rng.gen_range(-0.5f32..0.5f32);
-                let pos = Vec2::new(x, base_y + jitter);
-                self.particles[i] = Particle::new(pos);
-                spawned += 1;
+    }
+
+    /// Advances the simulation by one frame lasting `dt` seconds and renders
+    /// it into the canvas.
+    fn update(&mut self, dt: f32) {
+        let step_once = std::mem::take(&mut self.step_once);
+        if !self.sim.params.paused || step_once {
+            // While frozen, the particles already out finish their streamlines alone
+            let spawning = self.frozen.is_none() && !self.burst_shot;
+            if spawning && self.sim.params.spawn_rate > 0.0 {
+                // Cap the frame time so a stall doesn't dump a flood at once
+                self.spawn_carry += self.sim.params.spawn_rate * dt.min(0.25);
+                let count = self.spawn_carry.floor();
+                self.spawn_carry -= count;
+                self.sim.spawn_trickle(count as usize);
+            } else if spawning {
+                self.sim.spawn();
+            }
+            if self.spawn_held {
+                if let Some(pos) = self.cursor {
+                    self.sim.spawn_burst(pos, self.sim.params.mouse_burst / 4);
+                }
             }
-            i += 1;
+            self.sim.attractor = if self.attract_held { self.cursor } else { None };
+            self.sim.step();
         }
-        while spawned < count {
-            let t = spawned as f32 / count as f32;
-            let base_y = t * height_f;
-            let jitter = self.rng.gen_range(-0.5f32..0.5f32);
-            let pos = Vec2::new(x, base_y + jitter);
-            self.particles.push(Particle::new(pos));
-            spawned += 1;
+
+        let (width, height) = (self.sim.width(), self.sim.height());
+        self.sim.render_into(self.canvas.frame_mut(), width, height);
+    }
+
+    fn stats(&self) -> RenderStats {
+        RenderStats {
+            fps: self.frame_times.fps(),
+            particles: self.sim.live_particles(),
+            segments: self.sim.segments_drawn(),
         }
     }
 
-    fn step_particles(&mut self) {
-        let margin = 10.0;
-        let width_f = self.width as f32;
-        let height_f = self.height as f32;
+    /// Draws parameter values, FPS and the live particle count in the
+    /// top-left corner over a darkened panel.
+    fn draw_hud(&mut self) {
+        let stats = self.stats();
+        let p = &self.sim.params;
+        let mut lines = vec![
+            format!("SCALE {:.5},{:.5}", p.scale_x, p.scale_y),
+            format!("Z_STEP {:.5}", p.z_step),
+            format!("HUE_RATE {:.4}", p.hue_rate),
+            format!("FORCE {:.3}", p.force),
+            format!("WIND {:.2},{:.2}", p.wind.x, p.wind.y),
+            format!("VIEW {:.2}X {:.0},{:.0}", p.zoom, p.offset.x, p.offset.y),
+            format!("ROTATION {:.0}", p.field_rotation.to_degrees()),
+            format!("FRICTION {:.4}", p.friction),
+            format!("FADE {:.3} {:?}", p.fade, p.fade_mode),
+            format!("STROKE_ALPHA {:.2}", p.stroke_alpha),
+            format!("STEPS {}", p.steps_per_frame),
+            if p.spawn_rate > 0.0 {
+                format!("SPAWN {:.0}/S", p.spawn_rate)
+            } else {
+                format!("SPAWN {}", p.spawn_count)
+            },
+            format!("COLOR {:?}", p.color_mode),
+            format!("SAT {:.1}", p.sat),
+            format!("GAMMA {:.1}", p.gamma),
+            format!("SYMMETRY {}", p.symmetry),
+            format!("FPS {:.1}", stats.fps),
+            format!("PARTICLES {} PEAK {}", stats.particles, self.sim.particle_high_water()),
+        ];
+        if let ColorMode::Age = p.color_mode {
+            lines.push(format!("AGE_EASING {:?}", p.age_easing));
+        }
+        if p.show_density {
+            lines.push(format!("DENSITY_PEAK {}", self.sim.peak_density()));
+        }
+        if self.frozen.is_some() {
+            lines.push("FROZEN".to_string());
+        }
+        if self.burst_shot {
+            lines.push(format!("BURST {}", p.burst_count));
+        }
+        let swatch_label = "SWATCHES ";
+        let swatches = matches!(p.color_mode, ColorMode::Palette | ColorMode::Duotone);
+        let swatch_row = lines.len();
+        if swatches {
+            // Digits recolored in their swatch below, so the keys show what they pick
+            lines.push(format!("{}123456789", swatch_label));
+        }
+        // Field readout under the cursor, for reasoning about the flow
+        if let Some(pos) = self.cursor {
+            let dir = self.sim.field_dir(pos);
+            lines.push(format!("CURSOR {:.0},{:.0}", pos.x, pos.y));
+            lines.push(format!("ANGLE {:.1}", dir.y.atan2(dir.x).to_degrees()));
+            lines.push(format!("DIR {:.2},{:.2}", dir.x, dir.y));
+        }
 
-        for particle in &mut self.particles {
-            if !particle.alive {
-                continue;
+        let scale = 2 * self.canvas.scale();
+        let margin = 6 * self.canvas.scale();
+        let line_h = (font::GLYPH_H + 2) * scale;
+        let longest = lines.iter().map(|l| l.len()).max().unwrap_or(0) as u32;
+        let (width, height) = (self.sim.width(), self.sim.height());
+        let frame = self.canvas.frame_mut();
+        font::darken_rect(
+            frame,
+            width,
+            height,
+            0,
+            0,
+            longest * font::advance(scale) + 2 * margin,
+            lines.len() as u32 * line_h + 2 * margin,
+            0.3,
+        );
+        for (i, line) in lines.iter().enumerate() {
+            let y = margin + i as u32 * line_h;
+            font::draw_text(frame, width, height, margin, y, scale, line, (230, 230, 230));
+        }
+        if swatches {
+            let y = margin + swatch_row as u32 * line_h;
+            let x = margin + swatch_label.len() as u32 * font::advance(scale);
+            for (i, &rgb) in palette::SWATCHES.iter().enumerate() {
+                let digit = (i + 1).to_string();
+                let x = x + i as u32 * font::advance(scale);
+                font::draw_text(frame, width, height, x, y, scale, &digit, rgb);
             }
-            for _ in 0..self.params.steps_per_frame {
-                let prev = particle.pos;
-                let dir = noise_dir(&self.perlin, self.params.scale, self.params.z, particle.pos);
-                particle.vel += dir * self.params.force;
-                particle.vel *= self.params.friction;
-                particle.pos += particle.vel;
-                particle.age = particle.age.saturating_add(1);
-
-                // Determine color now (no frame borrow yet)
-                let color = match self.params.color_mode {
-                    ColorMode::Direction => {
-                        let angle = particle.vel.y.atan2(particle.vel.x);
-                        let mut hue = (angle / std::f32::consts::TAU).fract();
-                        if hue < 0.0 {
-                            hue += 1.0;
-                        }
-                        let speed     = particle.vel.length();
-                        let v = (speed * 0.5).clamp(0.1, 1.0);
-                        hsv_to_rgb(hue + self.params.z * 0.5, 1.0, v)
-                    }
-                    ColorMode::Age => {
-                        let hue = ((particle.age as f32) * 0.002 + self.params.z * 0.5).fract();
-                        let v = (particle.vel.length() * 0.5).clamp(0.1, 1.0);
-                        hsv_to_rgb(hue, 1.0, v)
-                    }
-                    ColorMode::Curl => {
-                        let eps = 2.0;
-                        let a0 = noise_angle(&self.perlin, self.params.scale, self.params.z, prev);
-                        let a1 = noise_angle(
-                            &self.perlin,
-                            self.params.scale,
-                            self.params.z,
-                            prev + Vec2::new(eps, 0.0),
-                        );
-                        let mut da = a1 - a0;
-                        while da > std::f32::consts::PI {
-                            da -= std::f32::consts::TAU;
-                        }
-                        while da < -std::f32::consts::PI {
-                            da += std::f32::consts::TAU;
-                        }
-                        let hue = (da.abs() / std::f32::consts::PI).clamp(0.0, 1.0);
-                        let v = (particle.vel.length() * 0.6).clamp(0.2, 1.0);
-                        hsv_to_rgb(hue, 1.0, v)
-                    }
-                };
+        }
+    }
 
-                // Borrow frame only for drawing
-                {
-                    let frame = self.pixels.frame_mut();
-                    draw_segment_additive(
-                        frame,
-                        self.width,
-                        self.height,
-                        prev,
-                        particle.pos,
-                        color,
-                    );
-                }
+    fn update_and_render(&mut self) {
+        let now = Instant::now();
+        let elapsed = (now - self.last_frame).as_secs_f32();
+        self.frame_times.push(elapsed);
+        self.last_frame = now;
 
-                if particle.pos.x < -margin
-                    || particle.pos.x > width_f + margin
-                    || particle.pos.y < -margin
-                    || particle.pos.y > height_f + margin
-                {
-                    particle.alive = false;
-                    break;
+        if std::mem::take(&mut self.burst_pending) {
+            self.start_burst();
+        }
+        self.update(self.fixed_dt.unwrap_or(elapsed));
+
+        // Overlays, HDR tone mapping, vignette and gamma are drawn only for
+        // presentation: the accumulation buffer is restored afterwards so they
+        // never build up under the fade.
+        let p = &self.sim.params;
+        let overlay = p.show_field || p.show_density || p.show_hud || self.sim.has_post_effects() || self.sim.is_hdr();
+        if overlay {
+            self.overlay_backup.clear();
+            self.overlay_backup.extend_from_slice(self.canvas.frame());
+        }
+        let (width, height) = (self.sim.width(), self.sim.height());
+        self.sim.apply_hdr_tone_map(self.canvas.frame_mut());
+        self.sim.apply_post_effects(self.canvas.frame_mut(), width, height);
+        if self.sim.params.show_density {
+            self.sim.draw_density(self.canvas.frame_mut(), width, height);
+        }
+        if self.sim.params.show_field {
+            self.sim.draw_field(self.canvas.frame_mut(), width, height);
+        }
+        if self.sim.params.show_hud {
+            self.draw_hud();
+        }
+
+        let rendered = self.canvas.render(self.gui.as_mut());
+
+        if overlay {
+            self.canvas.frame_mut().copy_from_slice(&self.overlay_backup);
+        }
+        if let Err(e) = rendered {
+            eprintln!("pixels.render() failed: {}", e);
+        } else {
+            self.frame_index += 1;
+            if let Some(profile) = &mut self.profile {
+                let (particles, segments) = (self.sim.live_particles(), self.sim.segments_drawn());
+                if let Err(e) = profile.record(self.frame_index, now.elapsed(), particles, segments) {
+                    eprintln!("profiling stopped: {}", e);
+                    self.profile = None;
+                }
+            }
+            if let Some(every) = self.autosave_every {
+                if self.frame_index.is_multiple_of(every) {
+                    if let Err(e) = self.save_png() {
+                        eprintln!("autosave failed: {}", e);
+                    }
                 }
             }
+            self.capture_gif_frame();
+            self.capture_mp4_frame();
+            if self.sim.recording_full() {
+                println!("SVG segment cap of {} reached", self.svg_max_segments);
+                self.finish_svg_recording();
+            }
         }
     }
+}
 
-    fn update_and_render(&mut self) {
-        // Fade globally
-        self.apply_fade();
+/// Scales a count by `factor`, always moving by at least one so small values
+/// don't get stuck, and clamps to `min..=max`.
+fn scale_count(n: usize, factor: f32, min: usize, max: usize) -> usize {
+    let scaled = (n as f32 * factor).round() as usize;
+    let stepped = if factor > 1.0 {
+        scaled.max(n + 1)
+    } else {
+        scaled.min(n.saturating_sub(1))
+    };
+    stepped.clamp(min, max.max(min))
+}
+
+fn write_gif(path: &str, frames: Vec<Frame>) -> Result<()> {
+    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    let mut encoder = GifEncoder::new_with_speed(file, 10);
+    encoder.set_repeat(Repeat::Infinite)?;
+    encoder.encode_frames(frames)?;
+    Ok(())
+}
+
+/// Frame time headless runs and warm-up simulate, from `--fps` or 60 fps,
+/// so time-based params behave as they would live and the same every run.
+fn headless_dt(opts: &CliOptions) -> f32 {
+    1.0 / opts.fps.unwrap_or(60) as f32
+}
 
-        // Update simulation
-        if !self.params.paused {
-            self.spawn_particles();
-            self.step_particles();
-            self.params.z += self.params.z_step;
+/// Runs the simulation for `frames` frames into an offscreen buffer, saving a
+/// PNG every `save_every` frames. Never touches winit, so it works without a
+/// display (or a GPU, unless `--gpu` asks for one).
+fn run_headless(opts: &CliOptions, frames: u64) -> Result<()> {
+    let (width, height) = render_size(opts);
+    let params = initial_params(opts, height)?;
+    let mut app = App::new(
+        Canvas::offscreen(width, height),
+        width,
+        height,
+        params,
+        seeds_from_cli(opts),
+    );
+    app.apply_cli_options(opts)?;
+    app.fixed_dt = Some(headless_dt(opts));
+    let keyframes = match &opts.keyframes {
+        Some(path) => Some(Keyframes::load(path, &app.sim.params)?),
+        None => None,
+    };
+    let save_every = opts.save_every;
+    for _ in 0..frames {
+        // Keyed by the number of the frame about to be drawn, as its PNG is named
+        if let Some(keyframes) = &keyframes {
+            app.sim.set_params(keyframes.params_at(app.frame_index + 1)?);
+        }
+        app.update_and_render();
+        if app.frame_index.is_multiple_of(save_every) {
+            app.save_png()?;
         }
+        if app.frame_index.is_multiple_of(100) {
+            let stats = app.stats();
+            println!(
+                "Frame {}/{}: {:.1} fps, {} particles, {} segments",
+                app.frame_index, frames, stats.fps, stats.particles, stats.segments
+            );
+        }
+    }
+    app.finish_mp4();
+    app.finish_svg_recording();
+    app.finish_trace();
+    app.finish_profile();
+    Ok(())
+}
 
-        if let Err(e) = self.pixels.render() {
-            eprintln!("pixels.render() failed: {}", e);
+/// Steps and draws `frames` frames offscreen, as `--headless` would but
+/// saving nothing, then prints how long they took.
+fn run_bench(opts: &CliOptions, frames: u64) -> Result<()> {
+    let (width, height) = render_size(opts);
+    let params = initial_params(opts, height)?;
+    let mut app = App::new(
+        Canvas::offscreen(width, height),
+        width,
+        height,
+        params,
+        seeds_from_cli(opts),
+    );
+    app.apply_cli_options(opts)?;
+    app.fixed_dt = Some(headless_dt(opts));
+    let mut times = Vec::with_capacity(frames as usize);
+    let (mut particles, mut segments) = (0, 0);
+    let start = Instant::now();
+    for _ in 0..frames {
+        let frame_start = Instant::now();
+        app.update_and_render();
+        times.push(frame_start.elapsed().as_secs_f64() * 1000.0);
+        particles += app.sim.live_particles();
+        segments += app.sim.segments_drawn();
+    }
+    let total = start.elapsed().as_secs_f64();
+    app.finish_profile();
+
+    times.sort_by(f64::total_cmp);
+    let percentile = |p: f64| times[((times.len() - 1) as f64 * p).round() as usize];
+    let mean = times.iter().sum::<f64>() / times.len() as f64;
+    let stepper = if app.sim.steps_on_gpu() { "GPU" } else { "CPU" };
+    println!(
+        "{} frames at {}x{}, stepped on the {} with {} threads, in {:.2} s ({:.1} fps)",
+        frames,
+        width,
+        height,
+        stepper,
+        rayon::current_num_threads(),
+        total,
+        frames as f64 / total
+    );
+    println!("{:>10} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8}", "", "mean", "min", "p50", "p90", "p99", "max");
+    println!(
+        "{:>10} {:>8.2} {:>8.2} {:>8.2} {:>8.2} {:>8.2} {:>8.2}",
+        "frame ms",
+        mean,
+        times[0],
+        percentile(0.5),
+        percentile(0.9),
+        percentile(0.99),
+        times[times.len() - 1]
+    );
+    println!(
+        "{:>10} {:>8} particles, {} segments per frame on average",
+        "",
+        particles / frames as usize,
+        segments / frames as usize
+    );
+    Ok(())
+}
+
+/// Headless grid over `--sweep-scale` x `--sweep-force`: each combination
+/// runs `frames` frames from a fresh simulation and saves one PNG named after
+/// its values, all into a single run folder.
+fn run_sweep(opts: &CliOptions, frames: u64) -> Result<()> {
+    let (width, height) = render_size(opts);
+    let base = initial_params(opts, height)?;
+    let or_base = |values: &[f32], base: f32| {
+        if values.is_empty() {
+            vec![base]
         } else {
-            self.frame_index += 1;
+            values.to_vec()
+        }
+    };
+    let scales = or_base(&opts.sweep_scale, base.scale());
+    let forces = or_base(&opts.sweep_force, base.force);
+    let seeds = seeds_from_cli(opts);
+    // Every combination starts clean, so nothing is resumed or recorded
+    let opts = CliOptions {
+        load_state: None,
+        record_mp4: None,
+        record_svg: false,
+        trace: None,
+        profile: None,
+        ..opts.clone()
+    };
+
+    let mut output = RunOutput::new(&opts.outdir);
+    for &scale in &scales {
+        for &force in &forces {
+            let mut params = base.clone();
+            params.set_scale(scale);
+            params.force = force;
+            let mut app = App::new(Canvas::offscreen(width, height), width, height, params, seeds);
+            app.apply_cli_options(&opts)?;
+            app.fixed_dt = Some(headless_dt(&opts));
+            app.output = output;
+            for _ in 0..frames {
+                app.update_and_render();
+            }
+            app.save_png_as(&format!("sweep_scale{}_force{}.png", scale, force))?;
+            output = app.output;
         }
     }
+    Ok(())
+}
+
+/// Size of the canvas headless runs draw into: `--render-size`, or the
+/// window size times `--render-scale`.
+fn render_size(opts: &CliOptions) -> (u32, u32) {
+    opts.render_size
+        .unwrap_or((opts.width * opts.render_scale, opts.height * opts.render_scale))
+}
+
+/// The `--field-image` gradient field, if one was given.
+fn field_image_from_cli(opts: &CliOptions) -> Result<Option<ImageField>> {
+    opts.field_image
+        .as_deref()
+        .map(|path| ImageField::load(path, opts.field_contour))
+        .transpose()
+        .map_err(Into::into)
+}
+
+/// Defaults for a canvas of the given height, overridden by `--config` if given.
+fn initial_params(opts: &CliOptions, height: u32) -> Result<Params> {
+    let params = Params::new(height);
+    let mut params = match &opts.config {
+        Some(path) => Params::load(path, &params)?,
+        None => params,
+    };
+    if let Some([lo, hi]) = opts.duotone {
+        params.color_mode = ColorMode::Duotone;
+        params.color_lo = lo;
+        params.color_hi = hi;
+    }
+    if opts.tileable {
+        params.tileable = true;
+    }
+    Ok(params)
 }
 
 fn main() -> Result<()> {
+    let opts = parse_cli_options()?;
+    if let Some(frames) = opts.sweep_frames {
+        return run_sweep(&opts, frames);
+    }
+    if let Some(frames) = opts.bench_frames {
+        return run_bench(&opts, frames);
+    }
+    if let Some(frames) = opts.headless_frames {
+        return run_headless(&opts, frames);
+    }
+
     let mode = parse_screensaver_mode();
 
     #[cfg(windows)]
@@ -520,7 +2493,10 @@ fn main() -> Result<()> {
             }
         }
         _ => {
-            builder = builder.with_inner_size(LogicalSize::new(WIDTH as f64, HEIGHT as f64));
+            builder = builder.with_inner_size(LogicalSize::new(opts.width as f64, opts.height as f64));
+            if opts.fullscreen {
+                builder = builder.with_fullscreen(Some(Fullscreen::Borderless(None)));
+            }
         }
     }
 
@@ -529,7 +2505,7 @@ fn main() -> Result<()> {
     // Hide cursor in screensaver/preview modes
     let exit_on_input = matches!(mode, ScreenSaverMode::Screensaver | ScreenSaverMode::Preview(_));
     if exit_on_input {
-        window.set_cursor_visible(build_config::SCREENSAVER_HIDE_CURSOR == false);
+        window.set_cursor_visible(!build_config::SCREENSAVER_HIDE_CURSOR);
     }
 
     let mut size = window.inner_size();
@@ -538,9 +2514,13 @@ fn main() -> Result<()> {
         size = winit::dpi::PhysicalSize::new(1, 1);
     }
     let surface_texture = SurfaceTexture::new(size.width, size.height, &window);
-    let pixels = match Pixels::new(size.width, size.height, surface_texture) {
+    let built = PixelsBuilder::new(size.width, size.height, surface_texture)
+        .enable_vsync(opts.vsync)
+        .build();
+    let pixels = match built {
         Ok(p) => p,
         Err(e) => {
+            eprintln!("pixels init error: {}", e);
             #[cfg(windows)]
             {
                 log_windows(&format!("pixels init error: {}", e));
@@ -549,19 +2529,46 @@ fn main() -> Result<()> {
             return Ok(());
         }
     };
-    let mut app = App::new(pixels, size.width, size.height);
+    let (canvas, width, height) = match opts.render_size {
+        Some((width, height)) => (Canvas::fixed(pixels, width, height, size.width, size.height)?, width, height),
+        None => {
+            let scale = opts.render_scale;
+            (Canvas::window(pixels, scale), size.width * scale, size.height * scale)
+        }
+    };
+    let params = initial_params(&opts, height)?;
+    let mut app = App::new(canvas, width, height, params, seeds_from_cli(&opts));
+    app.apply_cli_options(&opts)?;
+    app.gif_settings = opts.gif;
+    app.autosave_every = opts.autosave_every;
+    app.config_watch = opts.config.as_deref().map(ConfigWatch::new);
+    if !exit_on_input {
+        app.commands = Some(spawn_stdin_commands());
+    }
+    app.keymap = Keymap::load(KEYMAP_PATH)?;
+    if opts.gui {
+        app.gui = app.canvas.pixels().map(|pixels| Gui::new(&event_loop, &window, pixels));
+    }
 
     // Delay arming of input-exit to avoid immediate exit on first focus/move
     let start_time = Instant::now();
     let arm_delay_ms: u64 = build_config::EXIT_ARM_DELAY_MS as u64;
 
+    let frame_period = opts.fps.map(|fps| Duration::from_secs_f64(1.0 / fps as f64));
+    let max_frames = opts.max_frames;
+    let mut next_frame = Instant::now();
+
     event_loop.run(move |event, _, control_flow| {
-        *control_flow = ControlFlow::Poll;
+        *control_flow = match frame_period {
+            Some(_) => ControlFlow::WaitUntil(next_frame),
+            None => ControlFlow::Poll,
+        };
         match event {
+            // The panel's own clicks and drags go no further
+            Event::WindowEvent { event, .. } if app.gui.as_mut().is_some_and(|gui| gui.on_event(&event)) => {}
             Event::WindowEvent { event, .. } => match event {
-                WindowEvent::CloseRequested => {
-                    *control_flow = ControlFlow::Exit;
-                }
+                WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                WindowEvent::ModifiersChanged(modifiers) => app.modifiers = modifiers,
                 WindowEvent::KeyboardInput { input, .. } => {
                     if exit_on_input && start_time.elapsed() > Duration::from_millis(arm_delay_ms) {
                         *control_flow = ControlFlow::Exit;
@@ -571,18 +2578,20 @@ fn main() -> Result<()> {
                 }
                 WindowEvent::CursorMoved { .. }
                 | WindowEvent::MouseInput { .. }
-                | WindowEvent::MouseWheel { .. } => {
-                    if exit_on_input && start_time.elapsed() > Duration::from_millis(arm_delay_ms) {
-                        *control_flow = ControlFlow::Exit;
-                    }
+                | WindowEvent::MouseWheel { .. }
+                | WindowEvent::Focused(false)
+                    if exit_on_input && start_time.elapsed() > Duration::from_millis(arm_delay_ms) =>
+                {
+                    *control_flow = ControlFlow::Exit;
                 }
-                WindowEvent::Focused(false) => {
-                    if exit_on_input && start_time.elapsed() > Duration::from_millis(arm_delay_ms) {
-                        *control_flow = ControlFlow::Exit;
-                    }
+                WindowEvent::CursorMoved { position, .. } => {
+                    app.handle_cursor_moved(position.x as f32, position.y as f32);
+                }
+                WindowEvent::MouseInput { state, button, .. } => {
+                    app.handle_mouse_button(state, button);
                 }
                 WindowEvent::Resized(size) => {
-                    if let Err(e) = app.pixels.resize_surface(size.width, size.height) {
+                    if let Err(e) = app.canvas.resize_surface(size.width, size.height) {
                         eprintln!("pixels surface resize failed: {}", e);
                         #[cfg(windows)]
                         log_windows(&format!("resize_surface error: {}", e));
@@ -593,7 +2602,7 @@ fn main() -> Result<()> {
                 }
                 WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
                     let size = *new_inner_size;
-                    if let Err(e) = app.pixels.resize_surface(size.width, size.height) {
+                    if let Err(e) = app.canvas.resize_surface(size.width, size.height) {
                         eprintln!("pixels surface resize failed: {}", e);
                         #[cfg(windows)]
                         log_windows(&format!("scale_factor resize_surface error: {}", e));
@@ -605,67 +2614,40 @@ fn main() -> Result<()> {
                 _ => {}
             },
             Event::MainEventsCleared => {
-                window.request_redraw();
+                app.reload_config_if_changed();
+                app.run_pending_commands();
+                match frame_period {
+                    Some(period) => {
+                        let now = Instant::now();
+                        if now >= next_frame {
+                            window.request_redraw();
+                            // Skip ahead rather than bursting to catch up after a stall
+                            next_frame += period;
+                            if next_frame < now {
+                                next_frame = now + period;
+                            }
+                        }
+                        *control_flow = ControlFlow::WaitUntil(next_frame);
+                    }
+                    None => window.request_redraw(),
+                }
             }
             Event::RedrawRequested(_) => {
+                app.prepare_gui(&window);
                 app.update_and_render();
+                if max_frames.is_some_and(|n| app.frame_index >= n) {
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
+            // Covers every exit path, including screensaver input
+            Event::LoopDestroyed => {
+                app.finish_gif_recording();
+                app.finish_mp4();
+                app.finish_svg_recording();
+                app.finish_trace();
+                app.finish_profile();
             }
             _ => {}
         }
     });
 }
-
-fn noise_dir(perlin: &Perlin, scale: f32, z: f32, p: Vec2) -> Vec2 {
-    let n = perlin.get([(p.x * scale) as f64, (p.y * scale) as f64, z as f64]) as f32;
-    let angle = n * std::f32::consts::TAU;
-    Vec2::new(angle.cos(), angle.sin())
-}
-
-fn noise_angle(perlin: &Perlin, scale: f32, z: f32, p: Vec2) -> f32 {
-    let n = perlin.get([(p.x * scale) as f64, (p.y * scale) as f64, z as f64]) as f32;
-    n * std::f32::consts::TAU
-}
-
-fn draw_segment_additive(
-    frame: &mut [u8],
-    width: u32,
-    height: u32,
-    p0: Vec2,
-    p1: Vec2,
-    color: (u8, u8, u8),
-) {
-    let (r, g, b) = color;
-
-    let mut x0 = p0.x as i32;
-    let mut y0 = p0.y as i32;
-    let x1 = p1.x as i32;
-    let y1 = p1.y as i32;
-
-    let dx = (x1 - x0).abs();
-    let sx = if x0 < x1 { 1 } else { -1 };
-    let dy = -(y1 - y0).abs();
-    let sy = if y0 < y1 { 1 } else { -1 };
-    let mut err = dx + dy;
-
-    loop {
-        if x0 >= 0 && y0 >= 0 && (x0 as u32) < width && (y0 as u32) < height {
-            let idx = (((y0 as u32) * width + (x0 as u32)) * 4) as usize;
-            frame[idx] = frame[idx].saturating_add(r);
-            frame[idx + 1] = frame[idx + 1].saturating_add(g);
-            frame[idx + 2] = frame[idx + 2].saturating_add(b);
-            frame[idx + 3] = 255;
-        }
-        if x0 == x1 && y0 == y1 {
-            break;
-        }
-        let e2 = 2 * err;
-        if e2 >= dy {
-            err += dy;
-            x0 += sx;
-        }
-        if e2 <= dx {
-            err += dx;
-            y0 += sy;
-        }
-    }
-}
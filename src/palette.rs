@@ -0,0 +1,159 @@
+//! Multi-stop color gradients used by `ColorMode::Palette`.
+
+use crate::error::SimError;
+
+/// A gradient stop: position in 0..=1 and its color.
+pub type Stop = (f32, (u8, u8, u8));
+
+const VIRIDIS: &[Stop] = &[
+    (0.0, (68, 1, 84)),
+    (0.25, (59, 82, 139)),
+    (0.5, (33, 145, 140)),
+    (0.75, (94, 201, 98)),
+    (1.0, (253, 231, 37)),
+];
+
+const MAGMA: &[Stop] = &[
+    (0.0, (0, 0, 4)),
+    (0.25, (81, 18, 124)),
+    (0.5, (183, 55, 121)),
+    (0.75, (252, 137, 97)),
+    (1.0, (252, 253, 191)),
+];
+
+const WARM_COOL: &[Stop] = &[
+    (0.0, (36, 70, 180)),
+    (0.35, (90, 180, 220)),
+    (0.5, (235, 230, 220)),
+    (0.65, (245, 170, 80)),
+    (1.0, (200, 40, 30)),
+];
+
+/// Blue through gray to red, centered on zero for signed quantities; used
+/// by `ColorMode::Divergence` rather than cycled.
+pub const DIVERGING: &[Stop] = &[(0.0, (40, 110, 255)), (0.5, (60, 60, 60)), (1.0, (255, 70, 40))];
+
+/// Built-in palettes, cycled in order.
+pub const BUILTIN: &[(&str, &[Stop])] = &[
+    ("viridis", VIRIDIS),
+    ("magma", MAGMA),
+    ("warm-cool", WARM_COOL),
+];
+
+/// Colors offered for recoloring the gradient ends while running, picked
+/// with the number keys; ordered dark to light.
+pub const SWATCHES: [(u8, u8, u8); 9] = [
+    (18, 22, 64),
+    (0, 77, 90),
+    (80, 20, 90),
+    (200, 30, 50),
+    (255, 140, 40),
+    (255, 210, 80),
+    (120, 230, 180),
+    (100, 180, 255),
+    (250, 240, 225),
+];
+
+/// The two-stop gradient of `ColorMode::Duotone`.
+pub fn duotone(lo: (u8, u8, u8), hi: (u8, u8, u8)) -> [Stop; 2] {
+    [(0.0, lo), (1.0, hi)]
+}
+
+/// Linearly interpolates the color at `t` between the surrounding stops and
+/// scales it by brightness `v`. Stops must be sorted by position.
+pub fn sample(stops: &[Stop], t: f32, v: f32) -> (u8, u8, u8) {
+    let Some(&(first_pos, first)) = stops.first() else {
+        return (0, 0, 0);
+    };
+    let t = t.clamp(0.0, 1.0);
+    let mut rgb = first;
+    if t > first_pos {
+        rgb = stops[stops.len() - 1].1;
+        for pair in stops.windows(2) {
+            let (p0, c0) = pair[0];
+            let (p1, c1) = pair[1];
+            if t <= p1 {
+                let span = (p1 - p0).max(f32::EPSILON);
+                let f = ((t - p0) / span).clamp(0.0, 1.0);
+                let lerp = |a: u8, b: u8| a as f32 + (b as f32 - a as f32) * f;
+                rgb = (lerp(c0.0, c1.0) as u8, lerp(c0.1, c1.1) as u8, lerp(c0.2, c1.2) as u8);
+                break;
+            }
+        }
+    }
+    let v = v.clamp(0.0, 1.0);
+    let scale = |c: u8| ((c as f32) * v).round() as u8;
+    (scale(rgb.0), scale(rgb.1), scale(rgb.2))
+}
+
+/// Extracts a `colors`-stop gradient from the image at `path` by median cut
+/// over a sample of its pixels. Stops are ordered dark to light and evenly
+/// spaced, so a palette scalar sweeps from the image's shadows to its
+/// highlights.
+pub fn from_image(path: &str, colors: usize) -> Result<Vec<Stop>, SimError> {
+    let img = image::open(path).map_err(SimError::image("opening", path))?.into_rgb8();
+    // Every nth pixel is plenty to find the dominant colors
+    let stride = (img.pixels().len() / 20_000).max(1);
+    let samples: Vec<[u8; 3]> = img.pixels().step_by(stride).map(|p| p.0).collect();
+    if samples.is_empty() {
+        return Err(SimError::Invalid(format!("{} has no pixels", path)));
+    }
+
+    let mut boxes = vec![samples];
+    while boxes.len() < colors.max(1) {
+        // Split the box whose widest channel range, weighted by how many
+        // pixels it holds, is largest, so a few outliers don't win over a
+        // big varied region
+        let Some((index, channel, range)) = boxes
+            .iter()
+            .enumerate()
+            .map(|(i, b)| {
+                let (channel, range) = widest_channel(b);
+                (i, channel, range)
+            })
+            .max_by_key(|&(i, _, range)| range as usize * boxes[i].len())
+        else {
+            break;
+        };
+        if range == 0 {
+            break;
+        }
+        let mut b = boxes.swap_remove(index);
+        b.sort_unstable_by_key(|px| px[channel]);
+        let upper = b.split_off(b.len() / 2);
+        boxes.push(b);
+        boxes.push(upper);
+    }
+
+    let mut means: Vec<(u8, u8, u8)> = boxes
+        .iter()
+        .map(|b| {
+            let sum = b.iter().fold([0u64; 3], |acc, px| {
+                [acc[0] + px[0] as u64, acc[1] + px[1] as u64, acc[2] + px[2] as u64]
+            });
+            let n = b.len() as u64;
+            ((sum[0] / n) as u8, (sum[1] / n) as u8, (sum[2] / n) as u8)
+        })
+        .collect();
+    means.sort_by(|a, b| luma(*a).total_cmp(&luma(*b)));
+
+    let last = (means.len() - 1).max(1) as f32;
+    Ok(means.into_iter().enumerate().map(|(i, c)| (i as f32 / last, c)).collect())
+}
+
+/// The channel with the largest spread in `pixels`, and that spread.
+fn widest_channel(pixels: &[[u8; 3]]) -> (usize, u8) {
+    (0..3)
+        .map(|c| {
+            let (lo, hi) = pixels
+                .iter()
+                .fold((u8::MAX, u8::MIN), |(lo, hi), px| (lo.min(px[c]), hi.max(px[c])));
+            (c, hi.saturating_sub(lo))
+        })
+        .max_by_key(|&(_, range)| range)
+        .unwrap_or((0, 0))
+}
+
+fn luma(c: (u8, u8, u8)) -> f32 {
+    0.2126 * c.0 as f32 + 0.7152 * c.1 as f32 + 0.0722 * c.2 as f32
+}
@@ -0,0 +1,564 @@
+//! Tunable simulation parameters and the enums that select behavior.
+
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+use crate::build_config;
+use crate::error::SimError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ColorMode {
+    Direction,
+    Age,
+    Curl,
+    Speed,
+    Palette,
+    /// The palette scalar mapped straight from `Params::color_lo` to
+    /// `Params::color_hi`.
+    Duotone,
+    /// Divergence of the flow through `palette::DIVERGING`: sinks, where
+    /// particles gather, blue and sources, where they spread, red.
+    Divergence,
+}
+
+/// The per-segment quantity a color mode maps to color.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ScalarSource {
+    Direction,
+    Age,
+    Curl,
+    /// Speed relative to `Params::speed_ref`, slow to fast.
+    Speed,
+    /// Divergence of the flow direction, negative to positive around 0.5.
+    Divergence,
+}
+
+/// Where `Simulator::spawn` places each frame's new particles.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SpawnMode {
+    /// Evenly down the vertical center line.
+    Line,
+    /// Uniformly at random over the frame.
+    Uniform,
+    /// On a lattice sized to `spawn_count`.
+    Grid,
+    /// On a ring centered in the frame.
+    Circle,
+    /// Along the frame border.
+    Edges,
+}
+
+/// How the canvas lets go of old trails each frame.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum FadeMode {
+    /// Every pixel moves `fade` of the way toward the background.
+    Darken,
+    /// Nothing fades, whatever `fade` is set to, for long exposures.
+    Persist,
+    /// Every pixel is first replaced by the average of its 3x3
+    /// neighborhood, then darkened as in `Darken`, so old trails soften
+    /// into a glow as they dim.
+    Blur,
+}
+
+/// Which parts of the field `SpawnMode::Uniform` favors.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SpawnBias {
+    None,
+    /// Keeps a candidate position with probability `|noise|` there, so
+    /// particles gather where the field is strongest.
+    High,
+    /// Keeps it with probability `1 - |noise|`, toward the calm regions.
+    Low,
+}
+
+/// Where particles that die come back, if anywhere, when
+/// `Params::emitters` is set.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum EmitterMode {
+    /// Dead particles stay dead until spawning reuses their slots.
+    None,
+    /// Each takes the next emitter in turn.
+    Cycle,
+    /// Each picks an emitter at random.
+    Random,
+}
+
+/// Curve the age scalar is eased through on each trip around the hue wheel.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum AgeEasing {
+    Linear,
+    /// Holds each hue longer at the ends of the cycle.
+    InOut,
+    /// Lingers on the starting hue, then sweeps quickly through the rest.
+    Exponential,
+}
+
+/// What happens to a particle that reaches the edge of the frame.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum BoundaryMode {
+    Kill,
+    Wrap,
+    Bounce,
+}
+
+/// How a drawn segment combines with what is already in the frame.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum BlendMode {
+    /// Saturating add; dense regions build up toward white.
+    Additive,
+    /// Blend over the existing pixel with `Params::blend_alpha` opacity.
+    AlphaOver,
+    /// Per-channel maximum.
+    Max,
+}
+
+/// What each substep of a particle leaves on the canvas.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SplatMode {
+    /// A line from where it was to where it is.
+    Lines,
+    /// A soft round dab `Params::splat_radius` across where it is, for an
+    /// airbrushed look.
+    Splats,
+    Both,
+}
+
+/// How the scalar noise is turned into a flow direction.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum FieldMode {
+    /// The noise value is read as an angle.
+    Angle,
+    /// The noise is a stream function and the direction is its curl, giving
+    /// divergence-free flow with no sources or sinks.
+    Curl,
+}
+
+/// How each substep's velocity change is taken from the field.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Integrator {
+    /// One field lookup at the start of the substep.
+    Euler,
+    /// Looks the field up again half a substep ahead and steps with that,
+    /// which follows tight curves with less overshoot at high force for
+    /// twice the lookups.
+    Midpoint,
+}
+
+/// Remapping of noise lookup points around the frame center, applied before
+/// the noise is scaled and sampled.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DomainWarp {
+    None,
+    /// Samples at (radius, angle), giving radial flow mirrored across the
+    /// horizontal axis.
+    Polar,
+    /// Rotates each point by an angle growing with its radius, coiling the
+    /// field into a spiral.
+    Swirl,
+}
+
+/// An extra noise layer summed onto the base field, with its own frequency
+/// and animation speed.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct NoiseLayer {
+    pub scale: f32,
+    pub z: f32,
+    pub z_step: f32,
+    pub weight: f32,
+}
+
+/// A fixed point particles are pulled toward (or pushed from, when
+/// `strength` is negative) on top of the field.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Attractor {
+    pub pos: Vec2,
+    /// Force at `radius` and anywhere closer; it falls off with the square
+    /// of the distance beyond.
+    pub strength: f32,
+    pub radius: f32,
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum NoiseKind {
+    Perlin,
+    OpenSimplex,
+    Worley,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct Params {
+    /// Noise frequency along each axis; unequal values stretch the field
+    /// into streaks along the axis with the smaller scale.
+    pub scale_x: f32,
+    pub scale_y: f32,
+    /// Canvas positions are multiplied by this and shifted by `offset`
+    /// before the noise is sampled, framing a different part of the field
+    /// without changing what `scale` means: above 1 fits more of it on the
+    /// canvas. Particles still move in canvas space. With `tileable`, a zoom
+    /// other than 1 breaks the repeat.
+    pub zoom: f32,
+    pub offset: Vec2,
+    pub z: f32,
+    pub z_step: f32,
+    /// Running hue offset of the direction and age color modes, advanced by
+    /// `hue_rate` each frame independently of the field's z.
+    pub hue_shift: f32,
+    pub hue_rate: f32,
+    pub force: f32,
+    /// Range each particle's mass is drawn from at spawn; the field force is
+    /// divided by it. Equal ends give every particle that mass.
+    pub mass_range: (f32, f32),
+    /// Constant drift added to every particle's velocity each substep,
+    /// alongside the field force.
+    pub wind: Vec2,
+    pub friction: f32,
+    /// Cap on particle speed in pixels per reference substep (see
+    /// `Simulator::substep_dt`), so high force with low
+    /// friction can't fling particles across the frame in one segment.
+    pub max_speed: f32,
+    pub steps_per_frame: usize,
+    pub spawn_count: usize,
+    /// Particles spawned per second instead of `spawn_count` per frame, so
+    /// the density doesn't depend on the frame rate; 0 is off.
+    pub spawn_rate: f32,
+    pub spawn_mode: SpawnMode,
+    pub spawn_bias: SpawnBias,
+    /// Points dead particles respawn at straight away, per `emitter_mode`,
+    /// for continuous streams out of fixed nozzles.
+    pub emitters: Vec<Vec2>,
+    pub emitter_mode: EmitterMode,
+    /// Radius in pixels respawned particles are scattered over around their
+    /// emitter, so a stream is more than one line drawn over itself.
+    pub emitter_spread: f32,
+    /// Every this many frames, dead particles are dropped from the list
+    /// instead of waiting to be reused, so a long run doesn't keep paying
+    /// for its busiest moment; 0 is off.
+    pub compact_every: u32,
+    /// When above 0, z (and each layer's z, and the hue shift) sweeps out
+    /// and back instead of climbing forever, returning exactly to where it
+    /// started every this many frames, so a recording of that length loops
+    /// seamlessly. The sweep covers as much per cycle as `z_step` and
+    /// `hue_rate` would. The LFOs run a whole number of cycles per loop.
+    pub loop_frames: u32,
+    /// Reference substeps a particle lives before it dies; its trail tapers off over
+    /// the last stretch of that life.
+    pub max_age: u32,
+    pub fade: f32,
+    /// Per-channel fades used in place of `fade` where set, so trails drift
+    /// toward a tint as they age; e.g. a lower `fade_r` leaves warm ghosts.
+    /// Setting `fade` itself clears them.
+    pub fade_r: Option<f32>,
+    pub fade_g: Option<f32>,
+    pub fade_b: Option<f32>,
+    pub fade_mode: FadeMode,
+    /// Canvas color the frame is cleared to and fades toward. Light
+    /// backgrounds pair with `BlendMode::AlphaOver`, since adding to white
+    /// shows nothing.
+    pub bg: (u8, u8, u8),
+    /// Strength of the radial darkening toward the corners, applied to the
+    /// presented and exported frame only; 0 is off and 1 takes the corners
+    /// to black.
+    pub vignette: f32,
+    /// Output gamma for the presented and exported frame: each channel is
+    /// raised to `1 / gamma`, so above 1 brightens dense midtones. 1 is off.
+    pub gamma: f32,
+    /// With `--hdr`, how bright a channel maps to white when the HDR copy is
+    /// tone-mapped for display and the 16-bit export, in full 8-bit
+    /// channels; brighter ones clip.
+    pub hdr_white: f32,
+    pub color_mode: ColorMode,
+    /// Scalar mapped through the palette in `ColorMode::Palette` and
+    /// `ColorMode::Duotone`.
+    pub palette_source: ScalarSource,
+    /// Easing of the age scalar, which otherwise advances the hue linearly.
+    pub age_easing: AgeEasing,
+    /// Shadow and highlight colors of `ColorMode::Duotone`.
+    pub color_lo: (u8, u8, u8),
+    pub color_hi: (u8, u8, u8),
+    /// Speed that maps to the fast end of `ColorMode::Speed`.
+    pub speed_ref: f32,
+    /// Width of the range each particle's fixed hue offset is drawn from at
+    /// spawn, in turns of the hue wheel; 0 gives every particle the same
+    /// colors.
+    pub hue_jitter: f32,
+    /// How much of each particle's previous segment color carries into the
+    /// next, 0..1, so a trail's color drifts rather than flickers where
+    /// its hue jumps; 0 colors every segment afresh.
+    pub color_smoothing: f32,
+    /// Saturation of the HSV color modes; palettes keep their own colors.
+    pub sat: f32,
+    /// Range each color mode's brightness is remapped into, from its 0..1
+    /// output; narrow it for muted fields, or raise `val_min` to keep slow
+    /// segments from going dark.
+    pub val_min: f32,
+    pub val_max: f32,
+    pub noise_kind: NoiseKind,
+    pub field_mode: FieldMode,
+    pub integrator: Integrator,
+    pub domain_warp: DomainWarp,
+    pub boundary: BoundaryMode,
+    /// Make the picture a seamless tile: the field repeats over the frame
+    /// (through 4D noise, so both ways at once), particles wrap whatever
+    /// `boundary` says and strokes wrap round the edges. Domain warp,
+    /// symmetry and `--field-image` break the repeat.
+    pub tileable: bool,
+    /// Fraction of the normal velocity kept when bouncing off an edge.
+    pub restitution: f32,
+    pub antialiased: bool,
+    pub blend_mode: BlendMode,
+    pub blend_alpha: f32,
+    /// Scales how much each stroke adds, 0..1, so the canvas can build up
+    /// slowly without dimming the colors themselves.
+    pub stroke_alpha: f32,
+    /// Extra line width per unit of particle speed; 0 keeps 1px lines.
+    pub thickness: f32,
+    pub splat_mode: SplatMode,
+    /// Pixels from the center of a splat to where its gaussian falloff has
+    /// all but vanished.
+    pub splat_radius: f32,
+    /// Draws each substep's move as a Catmull-Rom curve through the
+    /// particle's neighboring positions instead of a straight line, which
+    /// rounds off the corners at low `steps_per_frame`. Each move is drawn
+    /// a substep late, once the position after it is known.
+    pub smooth_trails: bool,
+    /// Positions each particle keeps for `smooth_trails`, 3 up to
+    /// `MAX_TRAIL_LEN`. A curve leans on the two points either side of its
+    /// move, or only the one after it at 3.
+    pub trail_len: u32,
+    /// Dims each segment by `1 / (1 + k * b)`, where `b` is the brightness
+    /// already in the frame where it starts, so busy regions don't white
+    /// out. Never brightens; 0 is off.
+    pub density_compensation: f32,
+    /// Order of the kaleidoscope: above 1, every segment is also drawn
+    /// rotated to each of `symmetry` positions around the center, plus the
+    /// mirror image of each.
+    pub symmetry: u32,
+    pub show_field: bool,
+    /// Overlays how many live particles are in each cell, blue for few to
+    /// red for the most anywhere in the frame.
+    pub show_density: bool,
+    pub show_hud: bool,
+    // fBm layering of the base noise
+    pub octaves: u32,
+    pub lacunarity: f32,
+    pub persistence: f32,
+    /// Sums `1 - |noise|` per octave instead of the noise itself (ridged
+    /// multifractal), folding the field into sharp creases the flow lines
+    /// bunch along.
+    pub ridged: bool,
+    /// Radians added to every flow direction, turning the whole field: at
+    /// pi/2 flow out of a source becomes flow around it.
+    pub field_rotation: f32,
+    /// Fixed attractors and repulsors, summed into the field force.
+    pub attractors: Vec<Attractor>,
+    /// Particles a single-shot burst (the 1 key) spawns at once, laid out
+    /// per `spawn_mode`.
+    pub burst_count: usize,
+    // Mouse interaction
+    pub mouse_burst: usize,
+    pub attract_strength: f32,
+    pub attract_radius: f32,
+    // Sinusoidal modulation of force and scale: each is multiplied by
+    // `1 + amp * sin(TAU * rate * frame)` while `lfo` is on
+    pub lfo: bool,
+    pub force_lfo_amp: f32,
+    pub force_lfo_rate: f32,
+    pub scale_lfo_amp: f32,
+    pub scale_lfo_rate: f32,
+    pub paused: bool,
+    /// Layers added on top of the base `scale`/`z` field; empty by default.
+    pub layers: Vec<NoiseLayer>,
+}
+
+impl Params {
+    /// Build-time defaults, with the spawn count derived from the canvas height.
+    pub fn new(height: u32) -> Self {
+        let color_mode = match build_config::COLOR_MODE_STR {
+            "Age" => ColorMode::Age,
+            "Curl" => ColorMode::Curl,
+            "Speed" => ColorMode::Speed,
+            "Palette" => ColorMode::Palette,
+            "Duotone" => ColorMode::Duotone,
+            "Divergence" => ColorMode::Divergence,
+            _ => ColorMode::Direction,
+        };
+
+        let spawn_count_from_factor = ((height as f32) * build_config::SPAWN_COUNT_FACTOR)
+            .round()
+            .max(1.0) as usize;
+
+        Self {
+            scale_x: build_config::SCALE,
+            scale_y: build_config::SCALE,
+            zoom: 1.0,
+            offset: Vec2::ZERO,
+            z: build_config::Z,
+            z_step: build_config::Z_STEP,
+            // Half the z rate, matching the colors' old coupling to z
+            hue_shift: build_config::Z * 0.5,
+            hue_rate: build_config::Z_STEP * 0.5,
+            force: build_config::FORCE,
+            mass_range: (1.0, 1.0),
+            wind: Vec2::ZERO,
+            friction: build_config::FRICTION,
+            max_speed: 200.0,
+            steps_per_frame: build_config::STEPS_PER_FRAME as usize,
+            spawn_count: spawn_count_from_factor,
+            spawn_rate: 0.0,
+            compact_every: 600,
+            loop_frames: 0,
+            spawn_mode: SpawnMode::Line,
+            spawn_bias: SpawnBias::None,
+            emitters: Vec::new(),
+            emitter_mode: EmitterMode::None,
+            emitter_spread: 3.0,
+            max_age: 2000,
+            fade: build_config::FADE,
+            fade_r: None,
+            fade_g: None,
+            fade_b: None,
+            fade_mode: FadeMode::Darken,
+            bg: (0, 0, 0),
+            vignette: 0.0,
+            gamma: 1.0,
+            hdr_white: 4.0,
+            color_mode,
+            palette_source: ScalarSource::Direction,
+            age_easing: AgeEasing::Linear,
+            color_lo: (18, 22, 64),
+            color_hi: (255, 190, 110),
+            speed_ref: 20.0,
+            hue_jitter: 0.0,
+            color_smoothing: 0.0,
+            sat: 1.0,
+            val_min: 0.0,
+            val_max: 1.0,
+            noise_kind: NoiseKind::Perlin,
+            field_mode: FieldMode::Angle,
+            integrator: Integrator::Euler,
+            domain_warp: DomainWarp::None,
+            boundary: BoundaryMode::Kill,
+            tileable: false,
+            restitution: 0.8,
+            antialiased: false,
+            blend_mode: BlendMode::Additive,
+            blend_alpha: 0.35,
+            stroke_alpha: 1.0,
+            thickness: 0.0,
+            splat_mode: SplatMode::Lines,
+            splat_radius: 4.0,
+            smooth_trails: false,
+            trail_len: 4,
+            density_compensation: 0.0,
+            symmetry: 1,
+            show_field: false,
+            show_density: false,
+            show_hud: false,
+            octaves: 1,
+            lacunarity: 2.0,
+            persistence: 0.5,
+            ridged: false,
+            field_rotation: 0.0,
+            attractors: Vec::new(),
+            burst_count: 4000,
+            mouse_burst: 48,
+            attract_strength: 1.5,
+            attract_radius: 150.0,
+            lfo: false,
+            force_lfo_amp: 0.5,
+            force_lfo_rate: 0.004,
+            scale_lfo_amp: 0.25,
+            scale_lfo_rate: 0.0025,
+            paused: build_config::PAUSED,
+            layers: Vec::new(),
+        }
+    }
+
+    /// The fade of each of red, green and blue: `fade_r`, `fade_g` and
+    /// `fade_b`, or `fade` for those not set.
+    pub fn fade_rgb(&self) -> [f32; 3] {
+        [self.fade_r, self.fade_g, self.fade_b].map(|f| f.unwrap_or(self.fade))
+    }
+
+    /// Sets the fade of all three channels to `fade`, dropping any
+    /// per-channel overrides.
+    pub fn set_fade(&mut self, fade: f32) {
+        self.fade = fade;
+        self.fade_r = None;
+        self.fade_g = None;
+        self.fade_b = None;
+    }
+
+    /// Sets both axis scales to `scale`.
+    pub fn set_scale(&mut self, scale: f32) {
+        self.scale_x = scale;
+        self.scale_y = scale;
+    }
+
+    /// Multiplies both axis scales by `factor`, keeping their ratio.
+    pub fn set_scale_by(&mut self, factor: f32) {
+        self.scale_x *= factor;
+        self.scale_y *= factor;
+    }
+
+    /// Geometric mean of the axis scales, the isotropic scale the field's
+    /// overall feature size corresponds to.
+    pub fn scale(&self) -> f32 {
+        (self.scale_x * self.scale_y).sqrt()
+    }
+
+    /// Overlays the keys present in a TOML document onto these params; any
+    /// field the document leaves out keeps its current value.
+    pub fn merged_with_toml(&self, text: &str) -> Result<Params, SimError> {
+        let toml::Value::Table(mut table) = toml::Value::try_from(self).map_err(SimError::encode("params"))? else {
+            unreachable!("params serialize to a table");
+        };
+        let mut overrides: toml::Table = text.parse().map_err(SimError::decode("params"))?;
+        // Files from before the per-axis scales set both with `scale`
+        if let Some(scale) = overrides.remove("scale") {
+            for key in ["scale_x", "scale_y"] {
+                overrides.entry(key).or_insert_with(|| scale.clone());
+            }
+        }
+        // `fade` sets all three channels, bar any the document also sets
+        if overrides.contains_key("fade") {
+            for key in ["fade_r", "fade_g", "fade_b"] {
+                table.remove(key);
+            }
+        }
+        table.extend(overrides);
+        toml::Value::Table(table).try_into().map_err(SimError::decode("params"))
+    }
+
+    /// Copy with the LFOs applied for the given frame; the base values are
+    /// returned unchanged when `lfo` is off.
+    pub fn modulated(&self, frame: u64) -> Params {
+        let mut params = self.clone();
+        if self.lfo {
+            let n = self.loop_frames as u64;
+            let wave = |amp: f32, rate: f32| {
+                if n == 0 {
+                    return 1.0 + amp * (std::f32::consts::TAU * rate * frame as f32).sin();
+                }
+                // The nearest whole number of cycles per loop, at least one
+                // unless the LFO is still, so the last frame leads into the first
+                let cycles = if rate == 0.0 { 0.0 } else { (rate * n as f32).round().max(1.0) };
+                let phase = cycles * (frame % n) as f32 / n as f32;
+                1.0 + amp * (std::f32::consts::TAU * phase).sin()
+            };
+            params.force *= wave(self.force_lfo_amp, self.force_lfo_rate);
+            params.set_scale_by(wave(self.scale_lfo_amp, self.scale_lfo_rate));
+        }
+        params
+    }
+
+    pub fn load(path: &str, defaults: &Params) -> Result<Params, SimError> {
+        let text = std::fs::read_to_string(path).map_err(SimError::io("reading", path))?;
+        defaults.merged_with_toml(&text).map_err(|e| e.in_file(path))
+    }
+
+    pub fn to_toml(&self) -> Result<String, SimError> {
+        toml::to_string_pretty(self).map_err(SimError::encode("params"))
+    }
+}
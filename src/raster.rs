@@ -0,0 +1,358 @@
+//! Line rasterizers that accumulate segments into an RGBA8 frame, or into
+//! an unclipped float RGB buffer for HDR export.
+
+use glam::Vec2;
+
+use crate::params::BlendMode;
+
+/// Widest line `draw_segment_thick` will draw, so very fast particles don't
+/// blot out the frame.
+pub const MAX_LINE_WIDTH: f32 = 6.0;
+
+/// Signature shared by the single-pixel rasterizers.
+pub type DrawFn = fn(&mut [u8], u32, u32, Vec2, Vec2, (u8, u8, u8), Blend);
+
+/// How a drawn color combines with the pixel already in the frame.
+#[derive(Clone, Copy)]
+pub struct Blend {
+    pub mode: BlendMode,
+    /// Opacity of the new color in `BlendMode::AlphaOver`.
+    pub alpha: f32,
+    /// Multiplies the coverage of every stroke, whatever the mode.
+    pub stroke_alpha: f32,
+}
+
+impl Blend {
+    pub const ADDITIVE: Blend = Blend {
+        mode: BlendMode::Additive,
+        alpha: 1.0,
+        stroke_alpha: 1.0,
+    };
+
+    /// Combines `color` at `coverage` (0..=1) into the RGBA pixel `px`.
+    pub fn apply(self, px: &mut [u8], color: (u8, u8, u8), coverage: f32) {
+        let coverage = coverage.min(1.0) * self.stroke_alpha;
+        for (dst, c) in px.iter_mut().zip([color.0, color.1, color.2]) {
+            *dst = match self.mode {
+                BlendMode::Additive => dst.saturating_add(((c as f32) * coverage).round() as u8),
+                BlendMode::AlphaOver => {
+                    let a = self.alpha * coverage;
+                    (*dst as f32 + (c as f32 - *dst as f32) * a).round() as u8
+                }
+                BlendMode::Max => (*dst).max(((c as f32) * coverage).round() as u8),
+            };
+        }
+        px[3] = 255;
+    }
+
+    /// `apply` for an RGB pixel of an HDR buffer, in units where 1 is a full
+    /// 8-bit channel; additive blending is left to run past 1.
+    pub fn apply_hdr(self, px: &mut [f32], color: (u8, u8, u8), coverage: f32) {
+        let coverage = coverage.min(1.0) * self.stroke_alpha;
+        for (dst, c) in px.iter_mut().zip([color.0, color.1, color.2]) {
+            let c = c as f32 / 255.0;
+            *dst = match self.mode {
+                BlendMode::Additive => *dst + c * coverage,
+                BlendMode::AlphaOver => *dst + (c - *dst) * self.alpha * coverage,
+                BlendMode::Max => dst.max(c * coverage),
+            };
+        }
+    }
+}
+
+/// Index of pixel (x, y) in a `width` x `height` frame, or `None` when it
+/// lies outside the bounds.
+fn pixel_index(width: u32, height: u32, x: i32, y: i32) -> Option<usize> {
+    if x < 0 || y < 0 || (x as u32) >= width || (y as u32) >= height {
+        return None;
+    }
+    Some(((y as u32) * width + (x as u32)) as usize)
+}
+
+/// Single-pixel Bresenham line, clipped to the frame.
+pub fn draw_segment_bresenham(
+    frame: &mut [u8],
+    width: u32,
+    height: u32,
+    p0: Vec2,
+    p1: Vec2,
+    color: (u8, u8, u8),
+    blend: Blend,
+) {
+    bresenham_pixels(width, height, p0, p1, |i, coverage| {
+        blend.apply(&mut frame[i * 4..i * 4 + 4], color, coverage)
+    });
+}
+
+/// Calls `plot` with the index and coverage of every in-bounds pixel of the
+/// Bresenham line from `p0` to `p1`.
+pub fn bresenham_pixels(width: u32, height: u32, p0: Vec2, p1: Vec2, mut plot: impl FnMut(usize, f32)) {
+    let mut x0 = p0.x as i32;
+    let mut y0 = p0.y as i32;
+    let x1 = p1.x as i32;
+    let y1 = p1.y as i32;
+
+    // Both ends past the same edge: nothing to draw, so skip the walk
+    let (w, h) = (width as i32, height as i32);
+    if (x0 < 0 && x1 < 0) || (y0 < 0 && y1 < 0) || (x0 >= w && x1 >= w) || (y0 >= h && y1 >= h) {
+        return;
+    }
+
+    let dx = (x1 - x0).abs();
+    let sx = if x0 < x1 { 1 } else { -1 };
+    let dy = -(y1 - y0).abs();
+    let sy = if y0 < y1 { 1 } else { -1 };
+    let mut err = dx + dy;
+
+    loop {
+        if let Some(idx) = pixel_index(width, height, x0, y0) {
+            plot(idx, 1.0);
+        }
+        if x0 == x1 && y0 == y1 {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x0 += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y0 += sy;
+        }
+    }
+}
+
+/// Anti-aliased line using Xiaolin Wu's algorithm: each pixel along the line
+/// receives the color scaled by its fractional coverage.
+pub fn draw_segment_wu(
+    frame: &mut [u8],
+    width: u32,
+    height: u32,
+    p0: Vec2,
+    p1: Vec2,
+    color: (u8, u8, u8),
+    blend: Blend,
+) {
+    wu_pixels(width, height, p0, p1, |i, coverage| {
+        blend.apply(&mut frame[i * 4..i * 4 + 4], color, coverage)
+    });
+}
+
+/// Calls `plot` with the index and fractional coverage of every in-bounds
+/// pixel the Wu line from `p0` to `p1` touches.
+pub fn wu_pixels(width: u32, height: u32, p0: Vec2, p1: Vec2, mut plot: impl FnMut(usize, f32)) {
+    let fpart = |v: f32| v - v.floor();
+    let rfpart = |v: f32| 1.0 - fpart(v);
+
+    let (mut x0, mut y0, mut x1, mut y1) = (p0.x, p0.y, p1.x, p1.y);
+    let steep = (y1 - y0).abs() > (x1 - x0).abs();
+    if steep {
+        std::mem::swap(&mut x0, &mut y0);
+        std::mem::swap(&mut x1, &mut y1);
+    }
+    if x0 > x1 {
+        std::mem::swap(&mut x0, &mut x1);
+        std::mem::swap(&mut y0, &mut y1);
+    }
+    let mut plot = |x: i32, y: i32, coverage: f32| {
+        let (x, y) = if steep { (y, x) } else { (x, y) };
+        if coverage > 0.0 {
+            if let Some(idx) = pixel_index(width, height, x, y) {
+                plot(idx, coverage);
+            }
+        }
+    };
+
+    let dx = x1 - x0;
+    let dy = y1 - y0;
+    let gradient = if dx.abs() < f32::EPSILON { 1.0 } else { dy / dx };
+
+    // First endpoint
+    let xend = x0.round();
+    let yend = y0 + gradient * (xend - x0);
+    let xgap = rfpart(x0 + 0.5);
+    let xpxl1 = xend as i32;
+    let ypxl1 = yend.floor() as i32;
+    plot(xpxl1, ypxl1, rfpart(yend) * xgap);
+    plot(xpxl1, ypxl1 + 1, fpart(yend) * xgap);
+    let mut intery = yend + gradient;
+
+    // Second endpoint
+    let xend = x1.round();
+    let yend = y1 + gradient * (xend - x1);
+    let xgap = fpart(x1 + 0.5);
+    let xpxl2 = xend as i32;
+    let ypxl2 = yend.floor() as i32;
+    plot(xpxl2, ypxl2, rfpart(yend) * xgap);
+    plot(xpxl2, ypxl2 + 1, fpart(yend) * xgap);
+
+    for x in (xpxl1 + 1)..xpxl2 {
+        let y = intery.floor() as i32;
+        plot(x, y, rfpart(intery));
+        plot(x, y + 1, fpart(intery));
+        intery += gradient;
+    }
+}
+
+/// Draws a segment `line_width` pixels wide as parallel copies of `draw`
+/// offset along the segment normal. Widths of 1 or less fall through to a
+/// single line.
+#[allow(clippy::too_many_arguments)]
+pub fn draw_segment_thick(
+    frame: &mut [u8],
+    width: u32,
+    height: u32,
+    p0: Vec2,
+    p1: Vec2,
+    line_width: f32,
+    color: (u8, u8, u8),
+    blend: Blend,
+    draw: DrawFn,
+) {
+    for offset in line_offsets(p0, p1, line_width) {
+        draw(frame, width, height, p0 + offset, p1 + offset, color, blend);
+    }
+}
+
+/// `draw_segment_thick` into an HDR buffer of RGB floats, with the Wu
+/// rasterizer when `antialiased` and Bresenham otherwise.
+#[allow(clippy::too_many_arguments)]
+pub fn draw_segment_hdr(
+    hdr: &mut [f32],
+    width: u32,
+    height: u32,
+    p0: Vec2,
+    p1: Vec2,
+    line_width: f32,
+    color: (u8, u8, u8),
+    blend: Blend,
+    antialiased: bool,
+) {
+    for offset in line_offsets(p0, p1, line_width) {
+        let plot = |i: usize, coverage| blend.apply_hdr(&mut hdr[i * 3..i * 3 + 3], color, coverage);
+        if antialiased {
+            wu_pixels(width, height, p0 + offset, p1 + offset, plot);
+        } else {
+            bresenham_pixels(width, height, p0 + offset, p1 + offset, plot);
+        }
+    }
+}
+
+/// Stamps a soft round dab of `color` centered on `center`, its coverage
+/// falling off as a gaussian to nearly nothing at `radius`.
+pub fn draw_splat(
+    frame: &mut [u8],
+    width: u32,
+    height: u32,
+    center: Vec2,
+    radius: f32,
+    color: (u8, u8, u8),
+    blend: Blend,
+) {
+    splat_pixels(width, height, center, radius, |i, coverage| {
+        blend.apply(&mut frame[i * 4..i * 4 + 4], color, coverage)
+    });
+}
+
+/// Calls `plot` with the index and coverage of every in-bounds pixel
+/// within `radius` of `center`: 1 at the middle, about 1% at the rim.
+pub fn splat_pixels(width: u32, height: u32, center: Vec2, radius: f32, mut plot: impl FnMut(usize, f32)) {
+    let radius = radius.max(0.5);
+    // Three standard deviations out at the rim
+    let falloff = 4.5 / (radius * radius);
+    let (lo, hi) = ((center - radius).floor(), (center + radius).ceil());
+    for y in lo.y as i32..=hi.y as i32 {
+        for x in lo.x as i32..=hi.x as i32 {
+            let d_sq = (Vec2::new(x as f32 + 0.5, y as f32 + 0.5) - center).length_squared();
+            if d_sq > radius * radius {
+                continue;
+            }
+            if let Some(i) = pixel_index(width, height, x, y) {
+                plot(i, (-falloff * d_sq).exp());
+            }
+        }
+    }
+}
+
+/// Offsets along the segment normal of the parallel lines a `line_width`
+/// wide segment is drawn as; just zero for widths of 1 or less.
+fn line_offsets(p0: Vec2, p1: Vec2, line_width: f32) -> impl Iterator<Item = Vec2> {
+    let line_width = line_width.min(MAX_LINE_WIDTH);
+    let dir = p1 - p0;
+    let (normal, lines) = if line_width <= 1.0 || dir.length_squared() < f32::EPSILON {
+        (Vec2::ZERO, 1)
+    } else {
+        (dir.normalize().perp(), line_width.round() as i32)
+    };
+    (0..lines).map(move |k| normal * (k as f32 - (lines - 1) as f32 * 0.5))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const W: u32 = 8;
+    const H: u32 = 6;
+    const WHITE: (u8, u8, u8) = (255, 255, 255);
+
+    fn frame() -> Vec<u8> {
+        vec![0; (W * H * 4) as usize]
+    }
+
+    /// Coordinates of every pixel with a nonzero color channel.
+    fn lit(frame: &[u8]) -> Vec<(u32, u32)> {
+        frame
+            .chunks_exact(4)
+            .enumerate()
+            .filter(|(_, px)| px[..3].iter().any(|&c| c != 0))
+            .map(|(i, _)| (i as u32 % W, i as u32 / W))
+            .collect()
+    }
+
+    fn draw(frame: &mut [u8], p0: (f32, f32), p1: (f32, f32), color: (u8, u8, u8)) {
+        draw_segment_bresenham(frame, W, H, Vec2::from(p0), Vec2::from(p1), color, Blend::ADDITIVE);
+    }
+
+    #[test]
+    fn off_screen_segment_writes_nothing() {
+        let mut f = frame();
+        draw(&mut f, (-10.0, -3.0), (-2.0, 20.0), WHITE);
+        draw(&mut f, (2.0, 10.0), (30.0, 12.0), WHITE);
+        draw(&mut f, (-5.0, -5.0), (-5.0, -5.0), WHITE);
+        assert!(f.iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn straddling_segment_writes_only_in_bounds() {
+        let mut f = frame();
+        draw(&mut f, (-4.0, 2.0), (3.0, 2.0), WHITE);
+        assert_eq!(lit(&f), vec![(0, 2), (1, 2), (2, 2), (3, 2)]);
+
+        // Running past the right edge must not wrap onto the next row
+        let mut f = frame();
+        draw(&mut f, (5.0, 1.0), (12.0, 1.0), WHITE);
+        assert_eq!(lit(&f), vec![(5, 1), (6, 1), (7, 1)]);
+    }
+
+    #[test]
+    fn point_segment_writes_one_pixel() {
+        let mut f = frame();
+        draw(&mut f, (3.5, 4.2), (3.5, 4.2), (10, 20, 30));
+        assert_eq!(lit(&f), vec![(3, 4)]);
+        let idx = ((4 * W + 3) * 4) as usize;
+        assert_eq!(&f[idx..idx + 4], &[10, 20, 30, 255]);
+    }
+
+    #[test]
+    fn additive_saturates_instead_of_overflowing() {
+        let mut f = frame();
+        for _ in 0..4 {
+            draw(&mut f, (0.0, 0.0), (7.0, 5.0), (200, 100, 1));
+        }
+        for (x, y) in lit(&f) {
+            let idx = ((y * W + x) * 4) as usize;
+            assert_eq!(&f[idx..idx + 3], &[255, 255, 4]);
+        }
+    }
+}
@@ -0,0 +1,53 @@
+//! Vector export of recorded segments, e.g. for pen plotters.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use crate::error::SimError;
+use crate::Segment;
+
+/// Writes `segments` as an SVG of the given canvas size on a black
+/// background, one `<line>` per segment, grouped by stroke color so a
+/// plotter can take each color as one pen pass.
+pub fn write_svg(path: &Path, width: u32, height: u32, segments: &[Segment]) -> Result<(), SimError> {
+    let out = svg_text(width, height, segments).expect("formatting into a String can't fail");
+    std::fs::write(path, out).map_err(SimError::io("writing", path))
+}
+
+fn svg_text(width: u32, height: u32, segments: &[Segment]) -> Result<String, std::fmt::Error> {
+    let mut by_color: BTreeMap<(u8, u8, u8), Vec<&Segment>> = BTreeMap::new();
+    for seg in segments {
+        by_color.entry(seg.color).or_default().push(seg);
+    }
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+        w = width,
+        h = height
+    )?;
+    writeln!(out, r#"<rect width="100%" height="100%" fill="black"/>"#)?;
+    for ((r, g, b), segs) in by_color {
+        writeln!(
+            out,
+            r##"<g stroke="#{:02x}{:02x}{:02x}" stroke-linecap="round">"##,
+            r, g, b
+        )?;
+        for seg in segs {
+            write!(
+                out,
+                r#"<line x1="{:.2}" y1="{:.2}" x2="{:.2}" y2="{:.2}""#,
+                seg.p0.x, seg.p0.y, seg.p1.x, seg.p1.y
+            )?;
+            if seg.width != 1.0 {
+                write!(out, r#" stroke-width="{:.2}""#, seg.width)?;
+            }
+            writeln!(out, "/>")?;
+        }
+        writeln!(out, "</g>")?;
+    }
+    writeln!(out, "</svg>")?;
+    Ok(out)
+}
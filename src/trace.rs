@@ -0,0 +1,136 @@
+//! Paths of a few followed particles, sampled every substep, for study
+//! outside the renderer.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use glam::Vec2;
+use serde_json::json;
+
+use crate::error::SimError;
+use crate::Particle;
+
+/// Where a followed particle was at one substep.
+#[derive(Clone, Copy, Debug)]
+pub struct TracePoint {
+    /// Steps the simulation had taken when the point was recorded.
+    pub frame: u64,
+    /// Substep within that frame, 0 for the position it started the frame at.
+    pub substep: u32,
+    pub pos: Vec2,
+}
+
+/// Follows the first `count` particles to come alive, each from its first
+/// step until it dies.
+pub struct Tracer {
+    count: usize,
+    /// Path index of each particle still being followed, by its slot in
+    /// `Simulator::particles`.
+    open: HashMap<usize, usize>,
+    paths: Vec<Vec<TracePoint>>,
+}
+
+impl Tracer {
+    pub fn new(count: usize) -> Self {
+        Self {
+            count,
+            open: HashMap::new(),
+            paths: Vec::new(),
+        }
+    }
+
+    pub fn paths(&self) -> &[Vec<TracePoint>] {
+        &self.paths
+    }
+
+    /// Path index for the particle in `slot`, if it is being followed.
+    pub(crate) fn path_at(&self, slot: usize) -> Option<usize> {
+        self.open.get(&slot).copied()
+    }
+
+    /// Starts paths for live particles not yet followed, in slot order,
+    /// until `count` have been started.
+    pub(crate) fn adopt(&mut self, particles: &[Particle], frame: u64) {
+        for (slot, particle) in particles.iter().enumerate() {
+            if self.paths.len() >= self.count {
+                break;
+            }
+            if particle.alive && !self.open.contains_key(&slot) {
+                self.open.insert(slot, self.paths.len());
+                self.paths.push(vec![TracePoint {
+                    frame,
+                    substep: 0,
+                    pos: particle.pos,
+                }]);
+            }
+        }
+    }
+
+    /// Adds one particle's substeps from a step, ending its path if it died.
+    pub(crate) fn record(&mut self, slot: usize, points: Vec<TracePoint>, died: bool) {
+        if let Some(path) = self.path_at(slot) {
+            self.paths[path].extend(points);
+        }
+        if died {
+            self.open.remove(&slot);
+        }
+    }
+
+    /// Follows the live particles to the slots they move to when the dead
+    /// ones are dropped from `particles`.
+    pub(crate) fn compact(&mut self, particles: &[Particle]) {
+        let mut moved = HashMap::with_capacity(self.open.len());
+        let live = particles.iter().enumerate().filter(|(_, p)| p.alive);
+        for (new_slot, (slot, _)) in live.enumerate() {
+            if let Some(path) = self.path_at(slot) {
+                moved.insert(new_slot, path);
+            }
+        }
+        self.open = moved;
+    }
+
+    /// Ends every path, for when the particles are replaced wholesale.
+    pub(crate) fn close_all(&mut self) {
+        self.open.clear();
+    }
+
+    /// Writes one row per point: `particle,frame,substep,x,y`.
+    pub fn write_csv(&self, path: &Path) -> Result<(), SimError> {
+        let mut out = String::from("particle,frame,substep,x,y\n");
+        for (i, points) in self.paths.iter().enumerate() {
+            for p in points {
+                writeln!(out, "{},{},{},{:.3},{:.3}", i, p.frame, p.substep, p.pos.x, p.pos.y)
+                    .expect("formatting into a String can't fail");
+            }
+        }
+        std::fs::write(path, out).map_err(SimError::io("writing", path))
+    }
+
+    /// Writes a FeatureCollection of one LineString per particle, in pixel
+    /// coordinates with y down, tagged with the frames it spans. Particles
+    /// with fewer than two points are left out, since a LineString needs two.
+    pub fn write_geojson(&self, path: &Path) -> Result<(), SimError> {
+        let features: Vec<_> = self
+            .paths
+            .iter()
+            .enumerate()
+            .filter(|(_, points)| points.len() >= 2)
+            .map(|(i, points)| {
+                let coordinates: Vec<[f32; 2]> = points.iter().map(|p| p.pos.to_array()).collect();
+                json!({
+                    "type": "Feature",
+                    "properties": {
+                        "particle": i,
+                        "start_frame": points[0].frame,
+                        "end_frame": points[points.len() - 1].frame,
+                    },
+                    "geometry": { "type": "LineString", "coordinates": coordinates },
+                })
+            })
+            .collect();
+        let collection = json!({ "type": "FeatureCollection", "features": features });
+        let text = serde_json::to_string(&collection).map_err(SimError::encode("traces"))?;
+        std::fs::write(path, text).map_err(SimError::io("writing", path))
+    }
+}